@@ -0,0 +1,201 @@
+//! `cargo swiftpkg`: generates the xcframeworks and Swift packages as an explicit step, e.g. in CI,
+//! instead of from the crate's build script.
+
+use std::path::PathBuf;
+use std::process::exit;
+use clap::{Args, Parser, Subcommand};
+use colored::Colorize;
+use uniffi_swiftpkg_gen::*;
+
+#[derive(Parser)]
+#[command(name = "cargo", bin_name = "cargo")]
+enum Cargo {
+    /// Generates Swift packages for a UniFFI crate.
+    #[command(version)]
+    Swiftpkg(SwiftpkgArgs),
+}
+
+#[derive(Args)]
+struct SwiftpkgArgs {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Builds the xcframeworks without writing the Swift packages.
+    Build(Options),
+    /// Builds the xcframeworks and writes the Swift packages.
+    Package(Options),
+    /// Prints every command and file change of `package` as JSON, without running them.
+    Plan(Options),
+    /// Removes the build directory, with the intermediate libraries and xcframeworks in it.
+    Clean(Options),
+    /// Checks every tool and directory the build needs, with hints for what is missing.
+    Doctor(Options),
+}
+
+/// Maps one to one onto the `Builder` methods of the same name.
+#[derive(Args)]
+struct Options {
+    /// Path to the crate's Cargo.toml.
+    #[arg(long, default_value = "Cargo.toml")]
+    manifest_path: PathBuf,
+    /// Package of the workspace to generate for.
+    #[arg(short, long)]
+    package: Option<String>,
+    /// Library target of the package.
+    #[arg(long)]
+    lib_name: Option<String>,
+    /// Name of the Swift package, the library name in Pascal case by default.
+    #[arg(long)]
+    swift_package_name: Option<String>,
+    /// Directory the Swift packages are written to, `xcode` next to Cargo.toml by default.
+    #[arg(long)]
+    output: Option<String>,
+    /// Platform to build, e.g. `ios`, `ios_sim` or `osx`. Repeat for several.
+    #[arg(long = "platform", value_name = "PLATFORM")]
+    platforms: Vec<Platform>,
+    /// Build variant, `debug`, `release` or one of `[package.metadata.swiftpkg]`. Repeat for several.
+    #[arg(long = "variant", value_name = "VARIANT")]
+    variants: Vec<String>,
+    /// Comma separated cargo features, added to those of every variant.
+    #[arg(long, value_delimiter = ',')]
+    features: Vec<String>,
+    /// Disables the default features, for every variant.
+    #[arg(long)]
+    no_default_features: bool,
+    /// Minimum OS version of a platform, e.g. `ios=15.0`. Repeat for several.
+    #[arg(long = "minimum-version", value_name = "PLATFORM=VERSION", value_parser = parse_minimum_version)]
+    minimum_versions: Vec<(Platform, String)>,
+    /// UDL file or glob pattern relative to the crate, e.g. `src/**/*.udl`. Repeat for several.
+    #[arg(long = "udl", value_name = "PATTERN")]
+    udl_patterns: Vec<String>,
+    /// Glob pattern of UDL files to leave out. Repeat for several.
+    #[arg(long = "udl-exclude", value_name = "PATTERN")]
+    udl_excludes: Vec<String>,
+    /// Rustup toolchain, e.g. `stable` or `nightly-2026-09-01`.
+    #[arg(long, conflicts_with = "cargo")]
+    toolchain: Option<String>,
+    /// Cargo binary of a toolchain not managed by rustup.
+    #[arg(long)]
+    cargo: Option<PathBuf>,
+    /// Only verifies the installed Rust targets instead of installing them.
+    #[arg(long)]
+    offline: bool,
+    /// Directory for the per-target cargo builds, the bindings, the fat libraries and the xcframeworks.
+    #[arg(long)]
+    build_dir: Option<PathBuf>,
+    /// Number of targets compiled at once.
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+    /// Runs every stage, even those whose inputs are unchanged.
+    #[arg(long)]
+    force: bool,
+}
+
+fn parse_minimum_version(value: &str) -> std::result::Result<(Platform, String), String> {
+    let (platform, version) = value.split_once('=')
+        .ok_or_else(|| format!("Expected PLATFORM=VERSION, got '{}'", value))?;
+    let platform = platform.parse::<Platform>().map_err(|e| e.to_string())?;
+    Ok((platform, version.to_string()))
+}
+
+impl Options {
+    fn builder(self) -> Builder {
+        let mut builder = Builder::new()
+            .with_crate_manifest_path(self.manifest_path)
+            .with_offline(self.offline)
+            .with_jobs(self.jobs)
+            .with_force(self.force);
+        if let Some(package) = self.package {
+            builder = builder.with_crate_package_name(package);
+        }
+        if let Some(lib_name) = self.lib_name {
+            builder = builder.with_crate_lib_name(lib_name);
+        }
+        if let Some(name) = self.swift_package_name {
+            builder = builder.with_swift_package_name(name);
+        }
+        if let Some(output) = self.output {
+            builder = builder.with_swift_package_build_path(output);
+        }
+        if !self.platforms.is_empty() {
+            builder = builder.with_platforms(self.platforms);
+        }
+        builder = builder
+            .with_variant_names(self.variants)
+            .with_features(self.features)
+            .with_no_default_features(self.no_default_features);
+        for (platform, version) in self.minimum_versions {
+            builder = builder.with_minimum_version(platform, version);
+        }
+        for pattern in self.udl_patterns {
+            builder = builder.with_udl_pattern(pattern);
+        }
+        for pattern in self.udl_excludes {
+            builder = builder.with_udl_exclude(pattern);
+        }
+        if let Some(toolchain) = self.toolchain {
+            builder = builder.with_toolchain(toolchain);
+        }
+        if let Some(cargo) = self.cargo {
+            builder = builder.with_cargo(cargo);
+        }
+        if let Some(build_dir) = self.build_dir {
+            builder = builder.with_build_dir(build_dir);
+        }
+        builder
+    }
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Build(options) => {
+            let report = options.builder().try_build()?;
+            for path in report.xc_frameworks {
+                println!("{} {}", "Built".green().bold(), path.display());
+            }
+        }
+        Command::Package(options) => {
+            let report = options.builder().try_generate()?;
+            for path in report.swift_packages {
+                println!("{} {}", "Generated".green().bold(), path.display());
+            }
+        }
+        Command::Plan(options) => {
+            println!("{}", options.builder().plan()?.to_json());
+        }
+        Command::Clean(options) => {
+            for path in options.builder().clean()? {
+                println!("{} {}", "Removed".green().bold(), path.display());
+            }
+        }
+        Command::Doctor(options) => {
+            let diagnostics = options.builder().doctor();
+            for check in &diagnostics.checks {
+                let status = match check.status {
+                    CheckStatus::Pass => "pass".green().bold(),
+                    CheckStatus::Warn => "warn".yellow().bold(),
+                    CheckStatus::Fail => "fail".red().bold(),
+                };
+                println!("{} {}: {}", status, check.name.bold(), check.detail);
+                if let Some(hint) = &check.hint {
+                    println!("     {}", hint);
+                }
+            }
+            if diagnostics.has_failures() {
+                exit(1);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let Cargo::Swiftpkg(args) = Cargo::parse();
+    if let Err(e) = run(args.command) {
+        eprintln!("{} {}", "error:".red().bold(), e);
+        exit(1);
+    }
+}
@@ -0,0 +1,440 @@
+#[cfg(feature = "bindgen")]
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use super::build_context::BuildContext;
+use super::command_runner::ProcessCommand;
+use super::error::*;
+
+/// Program name the in-process bindgen is listed under in plans, fingerprints and errors.
+#[cfg(feature = "bindgen")]
+const LINKED_PROGRAM: &str = "uniffi_bindgen";
+
+/// Version requirement of the uniffi_bindgen dependency in Cargo.toml, which the lockfile can only
+/// resolve to a compatible version. A test keeps the two in sync.
+#[cfg(feature = "bindgen")]
+const LINKED_VERSION: &str = "0.28";
+
+/// What the Swift bindings are generated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BindingsSource<'a> {
+    Udl(&'a Path),
+    /// A compiled library of a crate exporting its interface with `#[uniffi::export]`, whose embedded
+    /// metadata describes it and the uniffi crates it depends on. This is uniffi's library mode.
+    Library(&'a Path),
+}
+
+/// The uniffi-bindgen the Swift bindings are generated with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Bindgen {
+    /// The `uniffi-bindgen` binary in `$CARGO_HOME/bin`, installed as `install_hint` describes.
+    #[cfg_attr(feature = "bindgen", allow(dead_code))]
+    Binary(PathBuf),
+    /// The uniffi_bindgen crate linked in through the `bindgen` feature, pinned by the lockfile.
+    #[cfg(feature = "bindgen")]
+    Linked,
+}
+
+impl Bindgen {
+    /// The library when the `bindgen` feature is enabled, the installed binary otherwise.
+    pub(crate) fn resolve() -> Result<Self> {
+        #[cfg(feature = "bindgen")]
+        return Ok(Bindgen::Linked);
+        #[cfg(not(feature = "bindgen"))]
+        return super::toolchain::Toolchain::cargo_home_bin("uniffi-bindgen").map(Bindgen::Binary);
+    }
+
+    /// Version of the bindgen, from `uniffi-bindgen --version` for the binary.
+    /// `None` if the output has no version in it.
+    pub(crate) fn version(&self, context: &BuildContext) -> Result<Option<String>> {
+        match self {
+            Bindgen::Binary(path) => {
+                let output = context.query(&ProcessCommand::new(path).arg("--version"))?;
+                Ok(output.stdout.split_whitespace()
+                    .find(|f| f.chars().next().is_some_and(|c| c.is_ascii_digit()))
+                    .map(|f| f.to_string()))
+            }
+            #[cfg(feature = "bindgen")]
+            Bindgen::Linked => Ok(Some(LINKED_VERSION.to_string())),
+        }
+    }
+
+    /// Fails with `Error::UniffiVersionMismatch` unless the bindgen generates code for the `uniffi_version` runtime.
+    pub(crate) fn check_version(&self, uniffi_version: &str, bindgen_version: &str) -> Result<()> {
+        if is_compatible(uniffi_version, bindgen_version) {
+            return Ok(());
+        }
+        Err(Error::UniffiVersionMismatch {
+            uniffi_version: uniffi_version.to_string(),
+            bindgen: self.to_string(),
+            bindgen_version: bindgen_version.to_string(),
+        })
+    }
+
+    /// `uniffi-bindgen generate` for one source, which for the linked bindgen only describes the call.
+    pub(crate) fn command(&self, source: &BindingsSource, out_dir: &Path) -> ProcessCommand {
+        let program = match self {
+            Bindgen::Binary(path) => ProcessCommand::new(path),
+            #[cfg(feature = "bindgen")]
+            Bindgen::Linked => ProcessCommand::new(LINKED_PROGRAM),
+        };
+        let command = match source {
+            BindingsSource::Udl(udl_file) => program.arg("generate").arg(udl_file),
+            BindingsSource::Library(library) => program.args(["generate", "--library"]).arg(library),
+        };
+        command.args(["--language", "swift"])
+            .arg("--out-dir").arg(out_dir)
+    }
+}
+
+impl std::fmt::Display for Bindgen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bindgen::Binary(path) => write!(f, "{}", path.display()),
+            #[cfg(feature = "bindgen")]
+            Bindgen::Linked => f.write_str("the uniffi_bindgen library of the `bindgen` feature"),
+        }
+    }
+}
+
+/// Whether code generated by one uniffi version works with the runtime of another. Releases are compatible
+/// within the same major version, or the same minor version before 1.0, as with cargo's caret requirements.
+pub(crate) fn is_compatible(uniffi_version: &str, bindgen_version: &str) -> bool {
+    let (uniffi, bindgen) = (version_parts(uniffi_version), version_parts(bindgen_version));
+    let significant = match uniffi.first() {
+        Some(0) => 2,
+        _ => 1,
+    };
+    uniffi.iter().take(significant).eq(bindgen.iter().take(significant))
+}
+
+/// How to install a `uniffi-bindgen` binary for the `uniffi_version` runtime, or for the crate's runtime
+/// when its version is unknown. The uniffi_bindgen crate stopped shipping the binary in 0.23.
+pub(crate) fn install_hint(uniffi_version: Option<&str>) -> String {
+    if let Some(version) = uniffi_version.filter(|f| version_parts(f) < vec![0, 23]) {
+        return format!("Install it with `cargo install uniffi_bindgen --version ^{}`.", version);
+    }
+    let requirement = uniffi_version.map(|f| format!("^{}", f))
+        .unwrap_or_else(|| "<the crate's uniffi version>".to_string());
+    let hint = format!("Build it from a bin crate named `uniffi-bindgen` that depends on \
+    `uniffi = {{ version = \"{}\", features = [\"cli\"] }}` and calls `uniffi::uniffi_bindgen_main()` from `main`, \
+    and install it with `cargo install --path <bin crate>`.", requirement);
+    match uniffi_version {
+        Some(_) => hint,
+        None => format!("{} Before uniffi 0.23, install it with `cargo install uniffi_bindgen --version <version>`.", hint),
+    }
+}
+
+/// Major, minor and patch of a version, each 0 when missing.
+fn version_parts(version: &str) -> Vec<u64> {
+    version.split(['.', '-', '+'])
+        .take(3)
+        .map(|f| f.parse::<u64>().unwrap_or(0))
+        .collect()
+}
+
+/// Writes `<namespace>.swift`, `<namespace>FFI.h` and `<namespace>FFI.modulemap` of `source` into `out_dir`,
+/// for every namespace of the library in library mode.
+///
+/// `crate_roots` maps the crates' library names to their directories, from which their `uniffi.toml`
+/// and, for a library, the UDL files its metadata refers to are read.
+#[cfg(feature = "bindgen")]
+pub(crate) fn generate_swift_bindings(source: &BindingsSource, out_dir: &Path, crate_name: &str,
+                                      crate_roots: BTreeMap<String, PathBuf>) -> Result<()> {
+    use camino::Utf8Path;
+    let (BindingsSource::Udl(input) | BindingsSource::Library(input)) = source;
+    let failed = |message: String| Error::Bindgen {
+        input: input.to_path_buf(),
+        message,
+    };
+    let utf8 = |path: &Path| Utf8Path::from_path(path)
+        .map(|f| f.to_path_buf())
+        .ok_or_else(|| failed(format!("{:?} is not valid UTF-8", path)));
+    std::fs::create_dir_all(out_dir).map_err(|e| Error::io(out_dir, e))?;
+    let generated = match source {
+        BindingsSource::Udl(udl_file) => uniffi_bindgen::generate_bindings(
+            &utf8(udl_file)?,
+            None,
+            uniffi_bindgen::bindings::SwiftBindingGenerator,
+            Some(&utf8(out_dir)?),
+            None,
+            Some(crate_name),
+            false,
+        ),
+        BindingsSource::Library(library) => uniffi_bindgen::library_mode::generate_bindings(
+            &utf8(library)?,
+            None,
+            &uniffi_bindgen::bindings::SwiftBindingGenerator,
+            &CrateRoots(crate_roots),
+            None,
+            &utf8(out_dir)?,
+            false,
+        ).map(|_| ()),
+    };
+    generated.map_err(|e| failed(format!("{:#}", e)))
+}
+
+/// Supplies the configuration of the crates in a library, as the uniffi-bindgen binary does through `cargo metadata`.
+#[cfg(feature = "bindgen")]
+struct CrateRoots(BTreeMap<String, PathBuf>);
+
+#[cfg(feature = "bindgen")]
+impl uniffi_bindgen::BindgenCrateConfigSupplier for CrateRoots {
+    fn get_toml(&self, crate_name: &str) -> anyhow::Result<Option<toml::value::Table>> {
+        let Some(path) = self.0.get(crate_name).map(|f| f.join("uniffi.toml")).filter(|f| f.is_file()) else {
+            return Ok(None);
+        };
+        Ok(Some(toml::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    fn get_udl(&self, crate_name: &str, udl_name: &str) -> anyhow::Result<String> {
+        let root = self.0.get(crate_name)
+            .ok_or_else(|| anyhow::anyhow!("Crate {} is not a dependency of the package", crate_name))?;
+        Ok(std::fs::read_to_string(root.join("src").join(format!("{}.udl", udl_name)))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use super::*;
+    use crate::{Builder, BuildVariant, Platform, RecordingCommandRunner};
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn describes_the_generation_as_a_command() {
+        let binary = Bindgen::Binary("/home/me/.cargo/bin/uniffi-bindgen".into());
+        assert_eq!(binary.command(&BindingsSource::Udl(Path::new("src/kit.udl")), Path::new("headers")).to_string(),
+                   "/home/me/.cargo/bin/uniffi-bindgen generate src/kit.udl --language swift --out-dir headers");
+        assert_eq!(binary.command(&BindingsSource::Library(Path::new("libkit.a")), Path::new("headers")).to_string(),
+                   "/home/me/.cargo/bin/uniffi-bindgen generate --library libkit.a --language swift --out-dir headers");
+    }
+
+    #[test]
+    fn compares_uniffi_versions() {
+        assert!(is_compatible("0.28.3", "0.28.0"));
+        assert!(is_compatible("0.28.3", "0.28"));
+        assert!(!is_compatible("0.16.0", "0.28.3"));
+        assert!(is_compatible("1.2.0", "1.0.1"));
+        assert!(!is_compatible("1.2.0", "2.0.0"));
+
+        let binary = Bindgen::Binary("uniffi-bindgen".into());
+        assert!(binary.check_version("0.28.3", "0.28.1").is_ok());
+        let error = binary.check_version("0.16.0", "0.28.3").unwrap_err();
+        assert!(matches!(error, Error::UniffiVersionMismatch { .. }));
+        assert!(error.to_string().contains("`cargo install uniffi_bindgen --version ^0.16.0`"));
+        let error = binary.check_version("0.27.0", "0.28.3").unwrap_err().to_string();
+        assert!(!error.contains("cargo install uniffi_bindgen"));
+        assert!(error.contains("`uniffi = { version = \"^0.27.0\", features = [\"cli\"] }`"));
+        assert!(error.contains("`cargo install --path <bin crate>`"));
+    }
+
+    #[cfg(feature = "bindgen")]
+    #[test]
+    fn linked_version_matches_the_manifest_requirement() {
+        let manifest: toml::Value = toml::from_str(include_str!("../Cargo.toml")).unwrap();
+        let requirement = manifest["dependencies"]["uniffi_bindgen"]["version"].as_str().unwrap();
+        assert_eq!(requirement, LINKED_VERSION, "update LINKED_VERSION along with the uniffi_bindgen dependency");
+    }
+
+    #[cfg(feature = "bindgen")]
+    #[test]
+    fn generates_swift_bindings_in_process() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"fixture_kit\"\nversion = \"0.1.0\"\n").unwrap();
+        let udl_file = dir.path().join("src").join("fixture_kit.udl");
+        std::fs::write(&udl_file, "namespace fixture_kit { u32 answer(); };\n").unwrap();
+
+        let out_dir = dir.path().join("headers");
+        generate_swift_bindings(&BindingsSource::Udl(&udl_file), &out_dir, "fixture_kit", BTreeMap::new()).unwrap();
+        for file in ["fixture_kit.swift", "fixture_kitFFI.h", "fixture_kitFFI.modulemap"] {
+            assert!(out_dir.join(file).is_file(), "missing {}", file);
+        }
+
+        std::fs::write(&udl_file, "namespace fixture_kit {").unwrap();
+        assert!(matches!(generate_swift_bindings(&BindingsSource::Udl(&udl_file), &out_dir, "fixture_kit", BTreeMap::new()),
+                         Err(Error::Bindgen { .. })));
+    }
+
+    #[cfg(not(feature = "bindgen"))]
+    #[test]
+    fn fails_before_building_when_uniffi_versions_differ() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        write_uniffi_dependency(dir.path(), "0.16.0");
+
+        let runner = Arc::new(RecordingCommandRunner::new()
+            .respond_with("uniffi-bindgen", fake_uniffi_bindgen));
+        let error = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner.clone())
+            .try_generate()
+            .unwrap_err();
+
+        match error {
+            Error::UniffiVersionMismatch { uniffi_version, bindgen_version, .. } => {
+                assert_eq!((uniffi_version.as_str(), bindgen_version.as_str()), ("0.16.0", "0.28.3"));
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        assert_eq!(runner.calls().len(), 1);
+    }
+
+    // A runner whose commands can't be spawned, as when uniffi-bindgen is not installed.
+    #[cfg(not(feature = "bindgen"))]
+    struct MissingBinaryRunner;
+
+    #[cfg(not(feature = "bindgen"))]
+    impl crate::CommandRunner for MissingBinaryRunner {
+        fn output(&self, _command: &ProcessCommand) -> std::io::Result<crate::CommandOutput> {
+            Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+        }
+    }
+
+    #[cfg(not(feature = "bindgen"))]
+    #[test]
+    fn reports_how_to_install_a_missing_uniffi_bindgen() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        write_uniffi_dependency(dir.path(), "0.16.0");
+
+        let error = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(MissingBinaryRunner)
+            .try_generate()
+            .unwrap_err();
+
+        match &error {
+            Error::BindgenNotFound { path, install_hint, .. } => {
+                assert!(path.ends_with("bin/uniffi-bindgen"));
+                assert_eq!(install_hint, "Install it with `cargo install uniffi_bindgen --version ^0.16.0`.");
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        assert!(error.to_string().ends_with("`cargo install uniffi_bindgen --version ^0.16.0`."));
+    }
+
+    // Runs the uniffi_bindgen library of the `bindgen` feature in place of the scripted binary.
+    #[cfg(feature = "bindgen")]
+    #[test]
+    fn fails_before_building_when_the_linked_bindgen_differs() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        write_uniffi_dependency(dir.path(), "0.16.0");
+
+        let runner = Arc::new(RecordingCommandRunner::new());
+        let error = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner.clone())
+            .try_generate()
+            .unwrap_err();
+
+        match error {
+            Error::UniffiVersionMismatch { uniffi_version, bindgen, bindgen_version } => {
+                assert_eq!((uniffi_version.as_str(), bindgen_version.as_str()), ("0.16.0", "0.28"));
+                assert!(bindgen.contains("`bindgen` feature"));
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        assert!(runner.calls().is_empty());
+    }
+
+    /// Turns the fixture crate into one exporting `answer` with `#[uniffi::export]`, and builds its library for
+    /// the host. The dependencies are built in a target dir under the system's temp dir, shared across runs.
+    #[cfg(feature = "bindgen")]
+    fn build_uniffi_library(dir: &Path) -> PathBuf {
+        let manifest = fs::read_to_string(dir.join("Cargo.toml")).unwrap()
+            .replace("[workspace]", "[dependencies]\nuniffi = { version = \"0.28\", default-features = false }\n\n[workspace]");
+        fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+        fs::write(dir.join("src").join("lib.rs"),
+                  "uniffi::setup_scaffolding!();\n\n#[uniffi::export]\npub fn answer() -> u32 {\n    42\n}\n").unwrap();
+        fs::remove_file(dir.join("src").join("fixture_kit.udl")).unwrap();
+
+        let target_dir = std::env::temp_dir().join("uniffi_swiftpkg_gen-fixtures");
+        let status = std::process::Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+            .args(["build", "--lib", "--manifest-path"])
+            .arg(dir.join("Cargo.toml"))
+            .arg("--target-dir").arg(&target_dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        target_dir.join("debug").join("libfixture_kit.a")
+    }
+
+    // Runs the uniffi_bindgen library of the `bindgen` feature on a library with uniffi's metadata, and reads the
+    // crate's uniffi.toml through the crate roots of `cargo metadata`.
+    #[cfg(feature = "bindgen")]
+    #[test]
+    fn generates_bindings_from_the_library_with_the_linked_bindgen() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let library = build_uniffi_library(dir.path());
+        fs::write(dir.path().join("uniffi.toml"), "[bindings.swift]\nmodule_name = \"FixtureKit\"\n").unwrap();
+        // Stands in for the Apple targets' builds with the library built for the host.
+        let cargo_build = move |command: &ProcessCommand| {
+            let output = fake_cargo_build(command);
+            let target = command.args.iter().skip_while(|f| f.as_str() != "--target-dir").nth(1).unwrap();
+            let triple = command.args.iter().skip_while(|f| f.as_str() != "--target").nth(1).unwrap();
+            fs::copy(&library, Path::new(target).join(triple).join("release").join("libfixture_kit.a")).unwrap();
+            output
+        };
+        let runner = Arc::new(RecordingCommandRunner::new()
+            .respond_with("build --locked", cargo_build)
+            .respond_with("lipo -create", fake_lipo)
+            .respond_with("xcodebuild", fake_xcodebuild));
+
+        let report = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner.clone())
+            .with_platforms([Platform::Ios])
+            .with_variants([BuildVariant::release()])
+            .try_generate()
+            .unwrap();
+
+        assert!(!runner.calls().iter().any(|f| f.program.contains("uniffi")));
+        let sources = report.swift_packages[0].join("Sources").join("FixtureKit");
+        assert!(fs::read_to_string(sources.join("FixtureKit.swift")).unwrap().contains("func answer()"));
+        assert!(sources.join("Headers").join("FixtureKitFFI.h").is_file());
+        let module_map = fs::read_to_string(sources.join("Headers").join("module.modulemap")).unwrap();
+        assert!(module_map.contains("module FixtureKitFFI {"));
+        assert!(!sources.join("fixture_kit.swift").exists());
+    }
+
+    #[cfg(not(feature = "bindgen"))]
+    #[test]
+    fn generates_bindings_from_the_library_without_udl_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        fs::remove_file(dir.path().join("src").join("fixture_kit.udl")).unwrap();
+        let runner = Arc::new(RecordingCommandRunner::new()
+            .respond_with("uniffi-bindgen", fake_uniffi_bindgen)
+            .respond_with("build --locked", fake_cargo_build)
+            .respond_with("lipo -create", fake_lipo)
+            .respond_with("xcodebuild", fake_xcodebuild));
+
+        let report = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner.clone())
+            .with_platforms([Platform::Ios, Platform::MacOs])
+            .with_variants([BuildVariant::release()])
+            .try_generate()
+            .unwrap();
+
+        let calls = runner.calls();
+        let bindgen = calls.iter().position(|f| f.args.first().map(|a| a == "generate").unwrap_or(false)).unwrap();
+        let builds = calls.iter().enumerate()
+            .filter(|(_, f)| f.args.contains(&"build".to_string()))
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>();
+        assert!(builds.iter().all(|f| *f < bindgen));
+        let library = PathBuf::from(&calls[bindgen].args[2]);
+        assert_eq!(calls[bindgen].args[1], "--library");
+        assert!(library.ends_with(Path::new("aarch64-apple-ios").join("release").join("libfixture_kit.a")));
+
+        let sources = report.swift_packages[0].join("Sources").join("FixtureKit");
+        assert!(sources.join("FixtureKit.swift").is_file());
+        assert!(sources.join("Headers").join("module.modulemap").is_file());
+    }
+}
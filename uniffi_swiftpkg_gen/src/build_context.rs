@@ -0,0 +1,179 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use super::command_builder::CommandBuilder;
+use super::command_runner::{CommandOutput, ProcessCommand};
+use super::error::*;
+use super::ext::*;
+use super::plan::{BuildPlan, FileOperation};
+
+/// Carries out the side effects of a generator run.
+///
+/// In dry-run mode every command and filesystem change is recorded into a `BuildPlan` instead.
+/// Read-only queries such as `rustup target list` still run, so the plan reflects the machine.
+#[derive(Debug, Clone)]
+pub(crate) struct BuildContext {
+    command: CommandBuilder,
+    plan: Option<Arc<Mutex<BuildPlan>>>,
+}
+
+impl BuildContext {
+    pub(crate) fn new(command: CommandBuilder) -> Self {
+        BuildContext {
+            command,
+            plan: None,
+        }
+    }
+
+    pub(crate) fn dry_run(command: CommandBuilder) -> Self {
+        BuildContext {
+            command,
+            plan: Some(Arc::new(Mutex::new(BuildPlan::default()))),
+        }
+    }
+
+    pub(crate) fn is_dry_run(&self) -> bool {
+        self.plan.is_some()
+    }
+
+    /// The plan recorded so far, empty unless in dry-run mode.
+    pub(crate) fn recorded_plan(&self) -> BuildPlan {
+        self.plan.as_ref()
+            .and_then(|f| f.lock().ok().map(|plan| plan.clone()))
+            .unwrap_or_default()
+    }
+
+    fn record<F: FnOnce(&mut BuildPlan)>(&self, update: F) -> bool {
+        match &self.plan {
+            Some(plan) => {
+                if let Ok(mut plan) = plan.lock() {
+                    update(&mut plan);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs a command that changes the system, streaming its output.
+    pub(crate) fn run(&self, command: ProcessCommand) -> Result<()> {
+        if self.record(|plan| plan.commands.push(command.clone())) {
+            return Ok(());
+        }
+        self.command.stream(&command)
+    }
+
+    /// Runs a command that changes the system, printing its output prefixed by `prefix` once it exits.
+    pub(crate) fn run_buffered(&self, command: ProcessCommand, prefix: &str) -> Result<()> {
+        if self.record(|plan| plan.commands.push(command.clone())) {
+            return Ok(());
+        }
+        self.command.buffered(&command, prefix)
+    }
+
+    /// Runs `run` in-process in place of `command`, which stands for it in the plan.
+    #[allow(unused)]
+    pub(crate) fn run_in_process<F: FnOnce() -> Result<()>>(&self, command: ProcessCommand, run: F) -> Result<()> {
+        if self.record(|plan| plan.commands.push(command.clone())) {
+            return Ok(());
+        }
+        run()
+    }
+
+    /// Runs a read-only command and returns its output, in dry-run mode as well.
+    pub(crate) fn query(&self, command: &ProcessCommand) -> Result<CommandOutput> {
+        self.command.output(command)
+    }
+
+    pub(crate) fn create_dir(&self, path: &Path) -> Result<()> {
+        if self.record(|plan| plan.file_operations.push(FileOperation::CreateDir { path: path.to_path_buf() })) {
+            return Ok(());
+        }
+        path.to_path_buf().create_dir_if_not_exist()
+    }
+
+    pub(crate) fn write_file(&self, path: &Path, content: String, overwrite: bool) -> Result<()> {
+        if self.record(|plan| plan.file_operations.push(FileOperation::Write { path: path.to_path_buf(), overwrite })) {
+            return Ok(());
+        }
+        path.to_path_buf().write_file(content, overwrite)
+    }
+
+    pub(crate) fn remove(&self, path: &Path) -> Result<()> {
+        if self.record(|plan| plan.file_operations.push(FileOperation::Remove { path: path.to_path_buf() })) {
+            return Ok(());
+        }
+        path.to_path_buf().remove_if_exist()
+    }
+
+    pub(crate) fn copy_recursively(&self, from: &Path, to: &Path) -> Result<()> {
+        if self.record(|plan| plan.file_operations.push(FileOperation::Copy { from: from.to_path_buf(), to: to.to_path_buf() })) {
+            return Ok(());
+        }
+        from.to_path_buf().copy_recursively_to(to)
+    }
+
+    pub(crate) fn copy_dir_contents(&self, from: &Path, to: &Path) -> Result<()> {
+        if self.record(|plan| plan.file_operations.push(FileOperation::Copy { from: from.join("*"), to: to.to_path_buf() })) {
+            return Ok(());
+        }
+        from.to_path_buf().copy_dir_contents_to(to)
+    }
+
+    pub(crate) fn move_path(&self, from: &Path, to: &Path) -> Result<()> {
+        if self.record(|plan| plan.file_operations.push(FileOperation::Move { from: from.to_path_buf(), to: to.to_path_buf() })) {
+            return Ok(());
+        }
+        from.to_path_buf().move_to(to)
+    }
+
+    /// Concatenates the files in `dir` matching `pattern` into `to`, in path order, and removes them.
+    pub(crate) fn merge_matches(&self, dir: &Path, pattern: &str, to: &Path) -> Result<()> {
+        if self.record(|plan| plan.file_operations.push(FileOperation::Merge { from: dir.join(pattern), to: to.to_path_buf() })) {
+            return Ok(());
+        }
+        let files = dir.to_path_buf().glob_files(pattern)?;
+        if files.is_empty() {
+            return Err(Error::io(dir.join(pattern), std::io::Error::new(std::io::ErrorKind::NotFound,
+                                                                        "No file matches the pattern")));
+        }
+        let mut merged: Vec<String> = vec![];
+        for file in &files {
+            let contents = std::fs::read_to_string(file).map_err(|e| Error::io(file, e))?;
+            merged.push(contents.trim_end().to_string());
+        }
+        to.to_path_buf().write_file(merged.join("\n\n") + "\n", true)?;
+        for file in files {
+            file.remove_if_exist()?;
+        }
+        Ok(())
+    }
+
+    /// Moves every file in `dir` matching `pattern` into the `to` directory.
+    pub(crate) fn move_matches_into(&self, dir: &Path, pattern: &str, to: &Path) -> Result<()> {
+        if self.record(|plan| plan.file_operations.push(FileOperation::Move { from: dir.join(pattern), to: to.to_path_buf() })) {
+            return Ok(());
+        }
+        for file in dir.to_path_buf().glob_files(pattern)? {
+            if let Some(file_name) = file.file_name() {
+                file.move_to(&to.join(file_name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes every file in `dir` matching `pattern`.
+    pub(crate) fn remove_matches(&self, dir: &Path, pattern: &str) -> Result<()> {
+        if self.record(|plan| plan.file_operations.push(FileOperation::Remove { path: dir.join(pattern) })) {
+            return Ok(());
+        }
+        for file in dir.to_path_buf().glob_files(pattern)? {
+            file.remove_if_exist()?;
+        }
+        Ok(())
+    }
+
+    /// Adds details to the plan, in dry-run mode only.
+    pub(crate) fn update_plan<F: FnOnce(&mut BuildPlan)>(&self, update: F) {
+        self.record(update);
+    }
+}
@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use super::cargo_options::CargoOptions;
+use super::error::*;
+
+/// A named configuration every target is compiled in, producing its own xcframework and Swift package.
+///
+/// ```
+/// use uniffi_swiftpkg_gen::{BuildVariant, CargoOptions};
+///
+/// let staging = BuildVariant::new("staging")
+///     .with_cargo_options(CargoOptions::new()
+///         .with_cargo_profile("release")
+///         .with_features(["staging-endpoints"]));
+/// assert_eq!(staging.suffix, "_staging");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildVariant {
+    pub name: String,
+    /// Appended to the xcframework and Swift package names.
+    pub suffix: String,
+    pub cargo: CargoOptions,
+}
+
+impl BuildVariant {
+    /// A variant built with the `dev` profile and suffixed with `_<name>`.
+    pub fn new(name: impl Into<String>) -> Self {
+        let name = name.into();
+        BuildVariant {
+            suffix: format!("_{}", name),
+            name,
+            cargo: CargoOptions::default(),
+        }
+    }
+
+    /// The `dev` profile, generated as `<Package>_debug`.
+    pub fn debug() -> Self {
+        BuildVariant::new("debug")
+    }
+
+    /// The `release` profile, generated as `<Package>`.
+    pub fn release() -> Self {
+        BuildVariant::new("release")
+            .with_suffix("")
+            .with_cargo_options(CargoOptions::new().with_cargo_profile("release"))
+    }
+
+    /// Variants built when none are selected through `Builder::with_variants`.
+    pub fn defaults() -> Vec<BuildVariant> {
+        vec![BuildVariant::debug(), BuildVariant::release()]
+    }
+
+    pub fn with_suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.suffix = suffix.into();
+        self
+    }
+
+    /// Replaces the cargo options. The variant keeps its profile unless `options` selects one.
+    pub fn with_cargo_options(mut self, mut options: CargoOptions) -> Self {
+        if options.profile.is_none() {
+            options.profile = self.cargo.profile.take();
+        }
+        self.cargo = options;
+        self
+    }
+
+    /// Checks that every variant can be written next to the others.
+    pub(crate) fn validate(variants: &[BuildVariant]) -> Result<()> {
+        if variants.is_empty() {
+            return Err(Error::Configuration {
+                message: "No build variant selected. Select at least one variant to build.".to_string(),
+            });
+        }
+        for (index, variant) in variants.iter().enumerate() {
+            let is_valid_name = !variant.name.is_empty() && variant.name.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !is_valid_name {
+                return Err(Error::Configuration {
+                    message: format!("Build variant name '{}' may only contain letters, digits, '-' and '_'.",
+                                     variant.name),
+                });
+            }
+            if let Some(other) = variants[..index].iter()
+                .find(|f| f.name == variant.name || f.suffix == variant.suffix) {
+                return Err(Error::Configuration {
+                    message: format!("Build variants '{}' and '{}' share a name or suffix, their packages would overwrite each other.",
+                                     other.name, variant.name),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+    use crate::{Builder, Platform, RecordingCommandRunner};
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn defaults_to_debug_and_release() {
+        let variants = BuildVariant::defaults();
+        assert!(BuildVariant::validate(&variants).is_ok());
+        assert_eq!(variants[0].suffix, "_debug");
+        assert_eq!(variants[0].cargo.cargo_profile(), "dev");
+        assert_eq!(variants[1].suffix, "");
+        assert_eq!(variants[1].cargo.cargo_profile(), "release");
+
+        let release = BuildVariant::release().with_cargo_options(CargoOptions::new().with_features(["ffi"]));
+        assert_eq!(release.cargo.cargo_profile(), "release");
+        assert_eq!(release.cargo.features, vec!["ffi"]);
+    }
+
+    #[test]
+    fn rejects_clashing_variants() {
+        assert!(BuildVariant::validate(&[]).is_err());
+        assert!(BuildVariant::validate(&[BuildVariant::new("qa/1")]).is_err());
+        assert!(BuildVariant::validate(&[BuildVariant::release(), BuildVariant::new("prod").with_suffix("")]).is_err());
+    }
+
+    #[test]
+    fn builds_one_package_per_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+
+        let plan = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new())
+            .with_platforms([Platform::Ios])
+            .with_variants([
+                BuildVariant::debug().with_cargo_options(CargoOptions::new().with_features(["logging"])),
+                BuildVariant::new("staging").with_cargo_options(CargoOptions::new()
+                    .with_cargo_profile("release-lto")
+                    .no_default_features()
+                    .with_extra_args(["--timings"])),
+            ])
+            .plan()
+            .unwrap();
+
+        let commands = plan.commands.iter().map(|f| f.to_string()).collect::<Vec<String>>();
+        assert!(commands.iter().any(|f| f.contains(" --lib --features logging --target aarch64-apple-ios ")));
+        assert!(commands.iter().any(|f| f.contains(" --lib --profile release-lto --no-default-features --timings --target ")));
+        let lipo = plan.commands.iter().rfind(|f| f.program == "lipo").unwrap();
+        assert!(lipo.args.last().unwrap().ends_with("aarch64-apple-ios/release-lto/libfixture_kit.a"));
+        assert_eq!(PathBuf::from(&lipo.args[2]),
+                   plan.build_dir.join("universal").join("staging").join("ios").join("libfixture_kit.a"));
+
+        let xcode = dir.path().join("xcode");
+        assert_eq!(plan.swift_packages, vec![xcode.join("FixtureKit_debug"), xcode.join("FixtureKit_staging")]);
+        assert!(plan.xc_frameworks[1].ends_with("FixtureKit_staging.xcframework"));
+    }
+}
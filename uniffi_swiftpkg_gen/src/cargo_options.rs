@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+/// Arguments of the `cargo build` that compiles each target of a `BuildVariant`.
+///
+/// ```
+/// use uniffi_swiftpkg_gen::CargoOptions;
+///
+/// let options = CargoOptions::new()
+///     .with_features(["ffi", "staging-endpoints"])
+///     .no_default_features()
+///     .with_cargo_profile("release-lto")
+///     .with_extra_args(["--config", "profile.release-lto.strip=true"]);
+/// assert_eq!(options.profile_dir(), "release-lto");
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CargoOptions {
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+    /// Cargo profile, `dev` when unset.
+    pub profile: Option<String>,
+    /// Appended to the `cargo build` command line as they are.
+    pub extra_args: Vec<String>,
+}
+
+impl CargoOptions {
+    pub fn new() -> Self {
+        CargoOptions::default()
+    }
+
+    pub fn with_features<I, S>(mut self, features: I) -> Self
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String> {
+        self.features.extend(features.into_iter().map(|f| f.into()));
+        self
+    }
+
+    pub fn no_default_features(mut self) -> Self {
+        self.no_default_features = true;
+        self
+    }
+
+    pub fn with_cargo_profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    pub fn with_extra_args<I, S>(mut self, args: I) -> Self
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String> {
+        self.extra_args.extend(args.into_iter().map(|f| f.into()));
+        self
+    }
+
+    /// The profile cargo builds with.
+    pub fn cargo_profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or("dev")
+    }
+
+    /// Directory of the target dir cargo writes the profile's artifacts to.
+    pub fn profile_dir(&self) -> String {
+        match self.cargo_profile() {
+            "dev" | "test" => "debug".to_string(),
+            "bench" => "release".to_string(),
+            profile => profile.to_string(),
+        }
+    }
+
+    /// Arguments selecting the profile and features, following `cargo build`.
+    pub(crate) fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = vec![];
+        match self.cargo_profile() {
+            "dev" => {}
+            "release" => args.push("--release".to_string()),
+            profile => args.extend(["--profile".to_string(), profile.to_string()]),
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        if !self.features.is_empty() {
+            args.extend(["--features".to_string(), self.features.join(",")]);
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CargoOptions;
+
+    #[test]
+    fn builds_cargo_arguments() {
+        assert!(CargoOptions::new().args().is_empty());
+        assert_eq!(CargoOptions::new().with_cargo_profile("release").args(), vec!["--release"]);
+        assert_eq!(CargoOptions::new().profile_dir(), "debug");
+
+        let options = CargoOptions::new()
+            .with_cargo_profile("release-lto")
+            .with_features(["a", "b"])
+            .no_default_features()
+            .with_extra_args(["--timings"]);
+        assert_eq!(options.args(),
+                   vec!["--profile", "release-lto", "--no-default-features", "--features", "a,b", "--timings"]);
+        assert_eq!(options.profile_dir(), "release-lto");
+    }
+}
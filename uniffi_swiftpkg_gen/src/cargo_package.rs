@@ -1,10 +1,12 @@
 use cargo_metadata::Target;
-use colored::Colorize;
 use convert_case::{Case, Casing};
 use pathdiff::diff_paths;
-use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
-use std::process::exit;
+use super::error::*;
+use super::fingerprint::Fingerprint;
+use super::config::PackageConfig;
+use super::udl::UdlSources;
+use super::plan::PlannedPackage;
 
 #[derive(Debug, std::cmp::Eq, std::cmp::PartialEq)]
 pub(crate) struct CargoPackage {
@@ -19,6 +21,12 @@ pub(crate) struct CargoPackage {
     pub(crate) udl_absolute_files_path: Vec<(PathBuf, String)>,
     pub(crate) udl_relative_files_path: Vec<(PathBuf, String)>,
     pub(crate) base_bundle_identifier: String,
+    /// Root of the workspace the crate belongs to.
+    pub(crate) workspace_root: PathBuf,
+    /// Cargo target directory of the workspace, honouring `CARGO_TARGET_DIR` and `build.target-dir`.
+    pub(crate) target_dir: PathBuf,
+    /// The `[package.metadata.swiftpkg]` table.
+    pub(crate) config: PackageConfig,
 }
 
 impl CargoPackage {
@@ -27,20 +35,22 @@ impl CargoPackage {
         crate_name: Option<&String>,
         lib_name: Option<&String>,
         swift_package_name: Option<&String>,
-        swift_package_path: &Path,
-    ) -> Self {
-        let (crate_package_name, crate_lib_name, lib_src_path) =
+        swift_package_path: Option<&Path>,
+        udl_sources: &UdlSources,
+    ) -> Result<Self> {
+        let (crate_package_name, crate_lib_name, lib_src_path, workspace_root, target_dir, config) =
             CargoPackage::validate_cargo_and_return(crate_path,
                                                     crate_name,
-                                                    lib_name);
+                                                    lib_name)?;
 
-        let cargo_base_dir = if crate_path.ends_with("Cargo.toml") {
-            crate_path
-                .parent()
-                .expect("Cargo.toml file has no Parent folder !!!!!!!!")
-                .to_path_buf()
-        } else {
-            crate_path.to_path_buf()
+        let cargo_base_dir = CargoPackage::manifest_dir(crate_path)?;
+
+        // Explicit arguments win over the configuration, which wins over the defaults.
+        let swift_package_name = swift_package_name.or(config.swift_package_name.as_ref());
+        let swift_package_path = match (swift_package_path, &config.output) {
+            (Some(path), _) => path.to_path_buf(),
+            (None, Some(output)) => cargo_base_dir.join(output),
+            (None, None) => cargo_base_dir.join("xcode"),
         };
 
         // Convert Xcode Project case to Pascal Format
@@ -56,21 +66,25 @@ impl CargoPackage {
             swift_package_path.join(&xcode_framework_name)
         };
         let cargo_relative_path_to_xcode_project =
-            diff_paths(&cargo_base_dir, &xcode_framework_path)
-                .expect("Unable to get relative path to Cargo");
+            CargoPackage::relative_path(&cargo_base_dir, &xcode_framework_path)?;
 
-        let udl_files_path = CargoPackage::find_udl_files(crate_path);
+        let udl_files_path = udl_sources.or(&config.udl).find(&cargo_base_dir)?
+            .into_iter()
+            .map(|path| {
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                (path, file_name)
+            })
+            .collect::<Vec<(PathBuf, String)>>();
 
         let mut udl_relative_files_path: Vec<(PathBuf, String)> = vec![];
         for (udl_path, filename) in &udl_files_path {
             udl_relative_files_path.push((
-                diff_paths(udl_path, &xcode_framework_path).expect("Getting relative path failed."),
+                CargoPackage::relative_path(udl_path, &xcode_framework_path)?,
                 filename.clone(),
             ));
         }
-        //println!("udl_relative_files_path: {:?}", udl_relative_files_path);
 
-        CargoPackage {
+        Ok(CargoPackage {
             swift_package_name: xcode_framework_name,
             swift_package_path: xcode_framework_path,
             crate_name: crate_package_name,
@@ -82,56 +96,196 @@ impl CargoPackage {
             udl_absolute_files_path: udl_files_path,
             udl_relative_files_path,
             base_bundle_identifier: "com.example".to_string(),
+            workspace_root,
+            target_dir,
+            config,
+        })
+    }
+
+    /// Default directory for the per-target cargo builds: `<target dir>/swiftpkg/<crate>-<hash>`.
+    ///
+    /// The hash of the workspace root keeps checkouts sharing a `CARGO_TARGET_DIR` apart.
+    pub(crate) fn default_build_dir(&self) -> PathBuf {
+        let workspace_hash = Fingerprint::new().write(self.workspace_root.to_string_lossy().as_bytes()).value();
+        self.target_dir
+            .join("swiftpkg")
+            .join(format!("{}-{:016x}", self.crate_name, workspace_hash))
+    }
+
+    /// Files whose settings apply to the crate's cargo builds: the manifests of the crate and of its workspace,
+    /// with their `[profile.*]` tables, and the cargo configuration of both directories.
+    pub(crate) fn build_config_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = vec![];
+        let paths = [&self.crate_dir, &self.workspace_root].into_iter()
+            .flat_map(|dir| [dir.join(".cargo").join("config.toml"), dir.join(".cargo").join("config")]);
+        for path in [self.crate_manifest_path.clone(), self.workspace_root.join("Cargo.toml")].into_iter().chain(paths) {
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+        files
+    }
+
+    /// Files a build script running the generator depends on: the UDL files, the crate's sources,
+    /// its manifest, the lockfile, and the cargo and toolchain configuration that exists.
+    pub(crate) fn rerun_if_changed(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.udl_absolute_files_path.iter().map(|(f, _)| f.clone()).collect();
+        paths.push(self.crate_dir.join("src"));
+        if let Some(lib_src_dir) = self.lib_src_path.parent() {
+            paths.push(lib_src_dir.to_path_buf());
+        }
+        paths.push(self.crate_manifest_path.clone());
+        paths.push(self.workspace_root.join("Cargo.lock"));
+        for dir in [&self.crate_dir, &self.workspace_root] {
+            paths.extend([
+                dir.join(".cargo").join("config.toml"),
+                dir.join(".cargo").join("config"),
+                dir.join("rust-toolchain.toml"),
+                dir.join("rust-toolchain"),
+            ]);
+        }
+
+        // Cargo considers a missing path changed and would rerun the build script every time.
+        let mut rerun_if_changed: Vec<PathBuf> = vec![];
+        for path in paths {
+            if path.exists() && !rerun_if_changed.contains(&path) {
+                rerun_if_changed.push(path);
+            }
         }
+        rerun_if_changed
+    }
+
+    /// Version of the uniffi runtime the crate resolves to, read from `cargo metadata` with dependencies.
+    /// `None` if the crate doesn't depend on uniffi directly.
+    pub(crate) fn uniffi_version(&self, offline: bool) -> Result<Option<String>> {
+        let cargo_metadata = self.resolved_metadata(offline)?;
+        let Some(package) = cargo_metadata.packages.iter()
+            .find(|f| f.name == self.crate_name && cargo_metadata.workspace_members.contains(&f.id)) else {
+            return Ok(None);
+        };
+        let dependencies = cargo_metadata.resolve.iter()
+            .flat_map(|f| f.nodes.iter())
+            .filter(|f| f.id == package.id)
+            .flat_map(|f| f.deps.iter())
+            .filter_map(|dep| cargo_metadata.packages.iter().find(|f| f.id == dep.pkg))
+            .collect::<Vec<&cargo_metadata::Package>>();
+        // Crates may depend on the runtime only through uniffi_core or uniffi_macros, released in lockstep.
+        Ok(["uniffi", "uniffi_core", "uniffi_macros"].iter()
+            .find_map(|name| dependencies.iter().find(|f| f.name == *name))
+            .map(|f| f.version.to_string()))
+    }
+
+    /// Directories of the crates the package resolves to, keyed by their library name, from which uniffi reads
+    /// their `uniffi.toml` in library mode.
+    #[cfg(feature = "bindgen")]
+    pub(crate) fn crate_roots(&self, offline: bool) -> Result<std::collections::BTreeMap<String, PathBuf>> {
+        let cargo_metadata = self.resolved_metadata(offline)?;
+        Ok(cargo_metadata.packages.iter()
+            .filter_map(|package| {
+                let lib = package.targets.iter().find(|f| f.kind.iter().any(|k| k.ends_with("lib")))?;
+                let root = package.manifest_path.parent()?.as_std_path().to_path_buf();
+                Some((lib.name.replace('-', "_"), root))
+            })
+            .collect())
+    }
+
+    /// Crates without UDL files export their interface with `#[uniffi::export]`, and their bindings are
+    /// generated from the compiled library.
+    pub(crate) fn is_library_mode(&self) -> bool {
+        self.udl_absolute_files_path.is_empty()
+    }
+
+    /// `cargo metadata` of the package including its dependencies, as pinned by Cargo.lock. `--locked` fails
+    /// instead of resolving the dependencies again, which could hit the network and rewrite the lockfile.
+    fn resolved_metadata(&self, offline: bool) -> Result<cargo_metadata::Metadata> {
+        let mut cargo_cmd = cargo_metadata::MetadataCommand::new();
+        cargo_cmd.manifest_path(&self.crate_manifest_path);
+        let mut options = vec!["--locked".to_string()];
+        if offline {
+            options.push("--offline".to_string());
+        }
+        cargo_cmd.other_options(options);
+        cargo_cmd.exec()
+            .map_err(|e| Error::Metadata {
+                manifest_path: self.crate_manifest_path.clone(),
+                message: e.to_string(),
+            })
+    }
+
+    pub(crate) fn planned(&self) -> PlannedPackage {
+        PlannedPackage {
+            crate_name: self.crate_name.clone(),
+            lib_name: self.target_name.clone(),
+            manifest_path: self.crate_manifest_path.clone(),
+            swift_package_name: self.swift_package_name.clone(),
+            swift_package_path: self.swift_package_path.clone(),
+            udl_files: self.udl_absolute_files_path.iter().map(|(f, _)| f.clone()).collect(),
+        }
+    }
+
+    fn manifest_dir(crate_path: &Path) -> Result<PathBuf> {
+        if crate_path.ends_with("Cargo.toml") {
+            crate_path
+                .parent()
+                .map(|f| f.to_path_buf())
+                .ok_or_else(|| Error::Metadata {
+                    manifest_path: crate_path.to_path_buf(),
+                    message: "Cargo.toml file has no Parent folder".to_string(),
+                })
+        } else {
+            Ok(crate_path.to_path_buf())
+        }
+    }
+
+    fn relative_path(path: &Path, base: &Path) -> Result<PathBuf> {
+        diff_paths(path, base).ok_or_else(|| Error::io(
+            path,
+            std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                format!("Unable to get relative path to {:?}", base)),
+        ))
     }
 
     fn validate_cargo_and_return(
         crate_path: &Path,
         package_name: Option<&String>,
         lib_name: Option<&String>,
-    ) -> (String, String, PathBuf) {
+    ) -> Result<(String, String, PathBuf, PathBuf, PathBuf, PackageConfig)> {
         let mut cargo_cmd = cargo_metadata::MetadataCommand::new();
         cargo_cmd.no_deps();
         cargo_cmd.manifest_path(crate_path);
 
-        let mut cargo_metadata = match cargo_cmd.exec() {
-            Ok(m) => m,
-            Err(e) => {
-                eprintln!(
-                    "{} error: Can't parse cargo metadata in {:?} because: {}",
-                    line!(), &crate_path, e
-                );
-                exit(1);
-            }
-        };
+        let mut cargo_metadata = cargo_cmd.exec()
+            .map_err(|e| Error::Metadata {
+                manifest_path: crate_path.to_path_buf(),
+                message: e.to_string(),
+            })?;
+
+        let workspace_root = cargo_metadata.workspace_root.clone().into_std_path_buf();
+        let target_dir = cargo_metadata.target_directory.clone().into_std_path_buf();
 
         let mut cargo_package = match package_name {
             Some(name) => {
                 cargo_metadata.packages.retain(|f| f.name == *name);
-                match cargo_metadata.packages.into_iter().next() {
-                    Some(package) => package,
-                    None => {
-                        eprintln!(
-                            "\nCheck your project Cargo.toml file or Specify a correct path.\n{} '{}' {} {:?}",
-                            "Specified package name".red(),
-                            name.bold(),
-                            "not found in your Cargo.toml file at:".red(),
-                            crate_path
-                        );
-                        exit(1)
-                    }
-                }
+                cargo_metadata.packages.into_iter().next()
+                    .ok_or_else(|| Error::PackageNotFound {
+                        name: name.clone(),
+                        manifest_path: crate_path.to_path_buf(),
+                    })?
             }
             None => {
-                match cargo_metadata.packages.into_iter().next() {
-                    Some(package) => package,
-                    None => {
-                        eprintln!("{}\nCheck your project Cargo.toml file or Specify path to the correct \
-                file or folder.\nPath:{:?}", "No Rust package found in your Cargo.toml file.".red(),
-                                  crate_path);
-                        exit(1);
-                    }
+                // A workspace lists every member, prefer the package the manifest belongs to.
+                let manifest_path = std::fs::canonicalize(crate_path).unwrap_or_else(|_| crate_path.to_path_buf());
+                if let Some(index) = cargo_metadata.packages.iter()
+                    .position(|f| f.manifest_path.as_std_path() == manifest_path) {
+                    cargo_metadata.packages.swap(0, index);
                 }
+                cargo_metadata.packages.into_iter().next()
+                    .ok_or_else(|| Error::Metadata {
+                        manifest_path: crate_path.to_path_buf(),
+                        message: "No Rust package found in your Cargo.toml file. \
+                        Check your project Cargo.toml file or Specify path to the correct file or folder."
+                            .to_string(),
+                    })?
             }
         };
 
@@ -140,118 +294,110 @@ impl CargoPackage {
             Some(ln) => {
                 cargo_package.targets.retain(|f| f.name == *ln);
                 if cargo_package.targets.is_empty() {
-                    eprintln!(
-                        "\n{} {} {}",
-                        "No library named: ".red(),
-                        ln.yellow().underline(),
-                        "found in all targets.".red()
-                    );
-                    eprintln!("Check provided library name and try again.\n");
-                    exit(1);
+                    return Err(Error::LibraryNotFound {
+                        name: ln.clone(),
+                        manifest_path: crate_path.to_path_buf(),
+                    });
                 }
                 CargoPackage::target_is_valid(
                     cargo_package.targets,
                     &cargo_package.name,
                     crate_path,
-                )
+                )?
             }
             None => CargoPackage::target_is_valid(
                 cargo_package.targets,
                 &cargo_package.name,
                 crate_path,
-            ),
+            )?,
         };
-        //println!("Detected package: {:?}", cargo_target);
-        (
+        Ok((
             cargo_package.name,
             cargo_target.name,
             PathBuf::from(cargo_target.src_path),
-        )
+            workspace_root,
+            target_dir,
+            PackageConfig::from_metadata(&cargo_package.metadata, crate_path)?,
+        ))
     }
 
     fn target_is_valid(
         mut targets: Vec<Target>,
         package_name: &str,
         cargo_manifest_path: &Path,
-    ) -> Target {
-        targets.retain(|f| f.kind.iter().any(|x| (x == "staticlib" || x == "lib")));
+    ) -> Result<Target> {
+        targets.retain(|f| f.kind.iter().any(|x| x == "staticlib" || x == "lib"));
         if targets.is_empty() {
-            eprintln!("\nNo target of type: [\"lib\"] found in cargo package.name: '{}' in file:{:?} \n{}\n\n",
-                      package_name.blue(), cargo_manifest_path, "Xcode framework project could only be created for Cargo 'library' targets.".red().bold());
-            exit(1);
+            return Err(Error::InvalidTarget {
+                package: package_name.to_string(),
+                manifest_path: cargo_manifest_path.to_path_buf(),
+                reason: "No target of type: [\"lib\"] found. \
+                Xcode framework project could only be created for Cargo 'library' targets.".to_string(),
+            });
         }
         targets.retain(|f| f.crate_types.contains(&String::from("staticlib")));
-        match targets.into_iter().next() {
-            Some(some_target) => some_target,
-            None => {
-                eprintln!("No crate_type of type: [\"staticlib\"] found in cargo package.name: '{}' in file:{:?} \n{}\n\n",
-                          package_name.blue(), cargo_manifest_path, ". Xcode framework project could only be created for Cargo library with crate_type containing 'staticlib'.".red().bold());
-                exit(1);
-            }
-        }
+        targets.into_iter().next()
+            .ok_or_else(|| Error::InvalidTarget {
+                package: package_name.to_string(),
+                manifest_path: cargo_manifest_path.to_path_buf(),
+                reason: "No crate_type of type: [\"staticlib\"] found. \
+                Xcode framework project could only be created for Cargo library with crate_type containing 'staticlib'."
+                    .to_string(),
+            })
     }
+}
 
-    fn find_udl_files(lib_src_path: &Path) -> Vec<(PathBuf, String)> {
-        let expected_udl_files_path = if lib_src_path.ends_with("Cargo.toml") {
-            lib_src_path
-                .parent()
-                .expect("Cargo.toml file has no Parent folder !!!!!!!!")
-                .join("src")
-        } else {
-            lib_src_path.join("src")
-        };
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use super::*;
+    use crate::test_fixtures::*;
 
-        let udl_files_path: Vec<(PathBuf, String)> =
-            match std::fs::read_dir(expected_udl_files_path) {
-                Ok(paths) => {
-                    paths
-                        .filter(|f| f.is_ok())
-                        .map(|f| f.expect("Failed to convert Directory Entry to path").path())
-                        .map(|f| {
-                            match (f.file_name(), f.extension()) {
-                                (Some(file_name), Some(ext)) => {
-                                    if ext == OsStr::new("udl") {
-                                        //println!("path: {:?}", f);
-                                        let m = file_name
-                                            .to_str()
-                                            .expect("Unable to get file name from UDL file path")
-                                            .to_string();
-                                        (Some(f.to_path_buf()), Some(m))
-                                    } else {
-                                        (None, None)
-                                    }
-                                }
-                                _ => (None, None),
-                            }
-                        })
-                }
-                Err(e) => {
-                    eprintln!(
-                        "Unable to open existing Xcode Project Directory at: {:?}\nError: {:?}",
-                        lib_src_path, e
-                    );
-                    exit(1);
-                }
-            }
-                .filter(|(a, _)| a.is_some())
-                .map(|(a, b)| {
-                    (
-                        a.expect("UDL file paths must not be empty"),
-                        b.expect("Unable to get file name from UDL file path"),
-                    )
-                })
-                .collect::<Vec<(PathBuf, String)>>();
-        if udl_files_path.is_empty() {
-            eprintln!("\n{}\n", "No UDL files found.".red());
-            eprintln!(
-                "Refer to Rust UniFFI: {}\n\n",
-                "https://mozilla.github.io/uniffi-rs/udl_file_spec.html"
-                    .underline()
-                    .bright_blue()
-            );
-            exit(1);
-        }
+    #[test]
+    fn lists_the_inputs_of_the_build_script() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        fs::create_dir_all(dir.path().join(".cargo")).unwrap();
+        fs::write(dir.path().join(".cargo").join("config.toml"), "").unwrap();
+        fs::write(dir.path().join("Cargo.lock"), "").unwrap();
+
+        let package = CargoPackage::new(&dir.path().join("Cargo.toml"), None, None, None, None, &UdlSources::default())
+            .unwrap();
+        assert_eq!(package.rerun_if_changed(), vec![
+            dir.path().join("src").join("fixture_kit.udl"),
+            dir.path().join("src"),
+            dir.path().join("Cargo.toml"),
+            dir.path().join("Cargo.lock"),
+            dir.path().join(".cargo").join("config.toml"),
+        ]);
+    }
+
+    #[test]
+    fn finds_udl_files_next_to_a_relative_manifest_path() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let manifest_path = pathdiff::diff_paths(dir.path().join("Cargo.toml"), env::current_dir().unwrap()).unwrap();
+        assert!(manifest_path.is_relative());
+
+        let package = CargoPackage::new(&manifest_path, None, None, None, None, &UdlSources::default()).unwrap();
+        let udl_file = manifest_path.with_file_name("src").join("fixture_kit.udl");
+        assert_eq!(package.udl_absolute_files_path, vec![(udl_file, "fixture_kit.udl".to_string())]);
+    }
+
+    #[test]
+    fn reads_the_uniffi_version_without_updating_the_lockfile() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        write_uniffi_dependency(dir.path(), "0.16.0");
+        let package = CargoPackage::new(&dir.path().join("Cargo.toml"), None, None, None, None, &UdlSources::default())
+            .unwrap();
+        assert_eq!(package.uniffi_version(true).unwrap().as_deref(), Some("0.16.0"));
 
-        udl_files_path
+        let lockfile = fs::read_to_string(dir.path().join("Cargo.lock")).unwrap();
+        fs::write(dir.path().join("uniffi").join("Cargo.toml"),
+                  "[package]\nname = \"uniffi\"\nversion = \"0.17.0\"\nedition = \"2021\"\n").unwrap();
+        assert!(matches!(package.uniffi_version(true), Err(Error::Metadata { .. })));
+        assert_eq!(fs::read_to_string(dir.path().join("Cargo.lock")).unwrap(), lockfile);
     }
 }
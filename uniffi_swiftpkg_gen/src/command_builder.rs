@@ -1,78 +1,71 @@
-use std::ffi::OsStr;
-use std::io;
-use std::process::{Command, ExitStatus, Output, Stdio};
-use std::io::{BufReader, BufRead};
-use super::ext::*;
-use colored::Colorize;
+use std::io::Write;
+use std::sync::Arc;
+use super::command_runner::*;
+use super::error::*;
 
-#[derive(Debug)]
+/// Issues commands through the configured `CommandRunner` and turns failures into `Error::Command`.
+#[derive(Clone)]
 pub(crate) struct CommandBuilder {
-    default_shell: String
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl std::fmt::Debug for CommandBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandBuilder").finish()
+    }
 }
 
 impl CommandBuilder {
     #[allow(unused)]
     pub(crate) fn new() -> Self {
-        let default_shell = Command::new("sh")
-            .arg("-c")
-            .args(["echo $SHELL"])
-            .output()
-            .expect("Unable to get default shell. Check your system if any shell is installed")
-            .utf8_string().first()
-            .unwrap().clone();
+        CommandBuilder::with_runner(Arc::new(SystemCommandRunner::new()))
+    }
+
+    pub(crate) fn with_runner(runner: Arc<dyn CommandRunner>) -> Self {
         CommandBuilder {
-            default_shell
+            runner
         }
     }
 
+    /// Runs the command and returns its captured output.
     #[allow(unused)]
-    pub(crate) fn args<I, S>(&self, params: I) -> io::Result<Output>
-        where
-            I: IntoIterator<Item = S>,
-            S: AsRef<OsStr> {
-        let mut params = params;
-
-        Command::new(&self.default_shell)
-            .arg("-c")
-            .args(params).output()
+    pub(crate) fn output(&self, command: &ProcessCommand) -> Result<CommandOutput> {
+        CommandBuilder::check(command, self.runner.output(command))
     }
 
+    /// Runs the command while streaming its stdout and stderr.
     #[allow(unused)]
-    pub(crate) fn args_stream<I, S>(&self, params: I) -> ExitStatus
-        where
-            I: IntoIterator<Item = S>,
-            S: AsRef<OsStr> {
-        let mut params = params;
-
-        let mut cmd = Command::new(&self.default_shell)
-            .arg("-c")
-            .args(params)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            //.stderr(Stdio::piped())
-            .spawn()
-            .unwrap();
-
-        {
-            let stdout = cmd.stdout.as_mut().unwrap();
-            let stdout_reader = BufReader::new(stdout);
-            let stdout_lines = stdout_reader.lines();
+    pub(crate) fn stream(&self, command: &ProcessCommand) -> Result<()> {
+        CommandBuilder::check(command, self.runner.stream(command)).map(|_| ())
+    }
 
-            for line in stdout_lines.filter_map(|line| line.ok()) {
-                println!("{:?}", line);
+    /// Runs the command with its output captured, then prints it at once with every line prefixed by `prefix`,
+    /// so the output of commands running in parallel doesn't interleave.
+    #[allow(unused)]
+    pub(crate) fn buffered(&self, command: &ProcessCommand, prefix: &str) -> Result<()> {
+        let output = self.runner.output(command);
+        if let Ok(output) = &output {
+            let mut stderr = std::io::stderr().lock();
+            for line in output.stdout.lines().chain(output.stderr.lines()) {
+                let _ = writeln!(stderr, "[{}] {}", prefix, line);
             }
         }
+        CommandBuilder::check(command, output).map(|_| ())
+    }
 
-
-        let status = cmd.wait().unwrap();
-
-        if let Some(err) = cmd.stderr {
-            let err_stream = BufReader::new(err).lines();
-            for line in err_stream.filter_map(|line| line.ok()) {
-                println!("ERR:::: {:?}", line.red());
-            }
+    fn check(command: &ProcessCommand, output: std::io::Result<CommandOutput>) -> Result<CommandOutput> {
+        match output {
+            Ok(output) if output.is_success() => Ok(output),
+            Ok(output) => Err(Error::Command {
+                command: command.to_string(),
+                status: output.status,
+                stderr: output.stderr,
+            }),
+            Err(e) => Err(Error::Command {
+                command: command.to_string(),
+                status: None,
+                stderr: e.to_string(),
+            }),
         }
-
-        status
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,299 @@
+use std::ffi::OsStr;
+use std::fmt::Formatter;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+
+/// A process invocation: the program, its argument vector and the environment it needs.
+///
+/// Arguments are passed to the process as-is, no shell is involved.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Variables set on top of the inherited environment.
+    pub envs: Vec<(String, String)>,
+}
+
+impl ProcessCommand {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        ProcessCommand {
+            program: program.as_ref().to_string_lossy().to_string(),
+            ..ProcessCommand::default()
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_string_lossy().to_string());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+        where
+            I: IntoIterator<Item = S>,
+            S: AsRef<OsStr> {
+        self.args.extend(args.into_iter().map(|f| f.as_ref().to_string_lossy().to_string()));
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    fn to_command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command.envs(self.envs.iter().map(|(k, v)| (k, v)));
+        command
+    }
+
+    fn quote(value: &str) -> String {
+        let is_plain = !value.is_empty() && value.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:+,@%".contains(c));
+        if is_plain {
+            value.to_string()
+        } else {
+            format!("'{}'", value.replace('\'', "'\\''"))
+        }
+    }
+}
+
+/// Renders the command as it would be typed in a POSIX shell, for logs and error messages.
+impl std::fmt::Display for ProcessCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts = self.envs.iter()
+            .map(|(k, v)| format!("{}={}", k, ProcessCommand::quote(v)))
+            .chain(std::iter::once(ProcessCommand::quote(&self.program)))
+            .chain(self.args.iter().map(|f| ProcessCommand::quote(f)))
+            .collect::<Vec<String>>();
+        f.write_str(&parts.join(" "))
+    }
+}
+
+/// Result of a command executed by a `CommandRunner`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// Exit code of the process, `None` if it was terminated by a signal.
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    /// A successful output with the given stdout.
+    pub fn success(stdout: impl Into<String>) -> Self {
+        CommandOutput {
+            status: Some(0),
+            stdout: stdout.into(),
+            stderr: String::new(),
+        }
+    }
+
+    /// A failed output with the given exit code and stderr.
+    pub fn failure(status: i32, stderr: impl Into<String>) -> Self {
+        CommandOutput {
+            status: Some(status),
+            stdout: String::new(),
+            stderr: stderr.into(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+/// Executes the commands issued by the generator.
+///
+/// The default `SystemCommandRunner` spawns real processes. Replace it through
+/// `Builder::with_command_runner` to observe or script the pipeline, e.g. with `RecordingCommandRunner`.
+pub trait CommandRunner: Send + Sync {
+    /// Runs the command and captures its output.
+    fn output(&self, command: &ProcessCommand) -> io::Result<CommandOutput>;
+
+    /// Runs the command while forwarding its output to the console.
+    fn stream(&self, command: &ProcessCommand) -> io::Result<CommandOutput> {
+        self.output(command)
+    }
+}
+
+/// Lets a runner be shared with the `Builder` while the caller keeps a handle to inspect it.
+impl<T: CommandRunner + ?Sized> CommandRunner for Arc<T> {
+    fn output(&self, command: &ProcessCommand) -> io::Result<CommandOutput> {
+        (**self).output(command)
+    }
+
+    fn stream(&self, command: &ProcessCommand) -> io::Result<CommandOutput> {
+        (**self).stream(command)
+    }
+}
+
+/// Spawns every command directly with `std::process::Command`.
+#[derive(Debug, Default, Clone)]
+pub struct SystemCommandRunner;
+
+impl SystemCommandRunner {
+    pub fn new() -> Self {
+        SystemCommandRunner
+    }
+}
+
+impl CommandRunner for SystemCommandRunner {
+    fn output(&self, command: &ProcessCommand) -> io::Result<CommandOutput> {
+        let output = command.to_command().output()?;
+        Ok(CommandOutput {
+            status: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+
+    /// Stderr is captured as well, so it can be reported if the command fails.
+    fn stream(&self, command: &ProcessCommand) -> io::Result<CommandOutput> {
+        let mut cmd = command.to_command()
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Stderr is drained on its own thread so neither pipe can fill up and block the child.
+        let stderr_thread = cmd.stderr.take().map(|err| {
+            std::thread::spawn(move || {
+                let mut captured = String::new();
+                for line in BufReader::new(err).lines().map_while(|line| line.ok()) {
+                    eprintln!("{}", line);
+                    captured.push_str(&line);
+                    captured.push('\n');
+                }
+                captured
+            })
+        });
+
+        let mut stdout = String::new();
+        if let Some(out) = cmd.stdout.as_mut() {
+            for line in BufReader::new(out).lines().map_while(|line| line.ok()) {
+                println!("{}", line);
+                stdout.push_str(&line);
+                stdout.push('\n');
+            }
+        }
+
+        let status = cmd.wait()?;
+        let stderr = stderr_thread
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+        Ok(CommandOutput {
+            status: status.code(),
+            stdout,
+            stderr,
+        })
+    }
+}
+
+type Responder = Box<dyn Fn(&ProcessCommand) -> CommandOutput + Send + Sync>;
+
+/// A `CommandRunner` that records every command instead of executing it.
+///
+/// Commands succeed with empty output unless a scripted response matches: the first
+/// response whose pattern is contained in the rendered command line wins.
+///
+/// ```
+/// use uniffi_swiftpkg_gen::{CommandOutput, CommandRunner, ProcessCommand, RecordingCommandRunner};
+///
+/// let runner = RecordingCommandRunner::new()
+///     .respond("rustup target list", CommandOutput::success("aarch64-apple-ios\n"));
+/// let command = ProcessCommand::new("rustup").args(["target", "list", "--installed"]);
+/// assert_eq!(runner.output(&command).unwrap().stdout, "aarch64-apple-ios\n");
+/// assert_eq!(runner.calls(), vec![command]);
+/// ```
+#[derive(Default)]
+pub struct RecordingCommandRunner {
+    responses: Vec<(String, Responder)>,
+    calls: Mutex<Vec<ProcessCommand>>,
+}
+
+impl RecordingCommandRunner {
+    pub fn new() -> Self {
+        RecordingCommandRunner::default()
+    }
+
+    /// Answers every command containing `pattern` with `output`.
+    pub fn respond(self, pattern: impl Into<String>, output: CommandOutput) -> Self {
+        self.respond_with(pattern, move |_| output.clone())
+    }
+
+    /// Answers every command containing `pattern` with the result of `responder`,
+    /// which can also simulate the side effects of the command.
+    pub fn respond_with<F>(mut self, pattern: impl Into<String>, responder: F) -> Self
+        where F: Fn(&ProcessCommand) -> CommandOutput + Send + Sync + 'static {
+        self.responses.push((pattern.into(), Box::new(responder)));
+        self
+    }
+
+    /// Every command received so far, in order.
+    pub fn calls(&self) -> Vec<ProcessCommand> {
+        self.calls.lock().map(|f| f.clone()).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Debug for RecordingCommandRunner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecordingCommandRunner")
+            .field("responses", &self.responses.iter().map(|(p, _)| p).collect::<Vec<&String>>())
+            .field("calls", &self.calls())
+            .finish()
+    }
+}
+
+impl CommandRunner for RecordingCommandRunner {
+    fn output(&self, command: &ProcessCommand) -> io::Result<CommandOutput> {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(command.clone());
+        }
+        let command_line = command.to_string();
+        Ok(self.responses.iter()
+            .find(|(pattern, _)| command_line.contains(pattern.as_str()))
+            .map(|(_, responder)| responder(command))
+            .unwrap_or_else(|| CommandOutput::success("")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_runner_scripts_responses_in_order() {
+        let runner = RecordingCommandRunner::new()
+            .respond("lipo", CommandOutput::failure(1, "lipo: can't open input file"))
+            .respond("lipo -info", CommandOutput::success("never used"));
+
+        let cargo = ProcessCommand::new("cargo").args(["build", "--target", "aarch64-apple-ios"]);
+        let lipo = ProcessCommand::new("lipo").args(["-info", "libfoo.a"]);
+        assert!(runner.stream(&cargo).unwrap().is_success());
+        let output = runner.stream(&lipo).unwrap();
+        assert_eq!(output.status, Some(1));
+        assert_eq!(output.stderr, "lipo: can't open input file");
+        assert_eq!(runner.calls(), vec![cargo, lipo]);
+    }
+
+    #[test]
+    fn display_quotes_arguments_with_spaces() {
+        let command = ProcessCommand::new("cargo")
+            .args(["build", "--manifest-path", "/Users/me/My Crate/Cargo.toml", "it's"])
+            .env("SKIP_UNIFFI_SWIFTPKG_GEN", "true");
+        assert_eq!(command.to_string(),
+                   "SKIP_UNIFFI_SWIFTPKG_GEN=true cargo build --manifest-path '/Users/me/My Crate/Cargo.toml' 'it'\\''s'");
+    }
+
+    #[test]
+    fn system_runner_passes_arguments_verbatim() {
+        let output = SystemCommandRunner::new()
+            .output(&ProcessCommand::new("printf").args(["%s|%s", "a b", "$HOME"]))
+            .unwrap();
+        assert!(output.is_success());
+        assert_eq!(output.stdout, "a b|$HOME");
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use super::build_variant::BuildVariant;
+use super::cargo_options::CargoOptions;
+use super::error::*;
+use super::platform::{MinimumVersions, Platform};
+use super::udl::UdlSources;
+
+/// The `[package.metadata.swiftpkg]` table of Cargo.toml, as written.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct RawConfig {
+    swift_package_name: Option<String>,
+    output: Option<PathBuf>,
+    platforms: Option<Vec<String>>,
+    variants: Option<Vec<RawVariant>>,
+    features: Vec<String>,
+    no_default_features: bool,
+    minimum_versions: BTreeMap<String, String>,
+    udl: Vec<String>,
+    udl_exclude: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawVariant {
+    name: String,
+    suffix: Option<String>,
+    profile: Option<String>,
+    #[serde(default)]
+    features: Vec<String>,
+    #[serde(default)]
+    no_default_features: bool,
+    #[serde(default)]
+    extra_args: Vec<String>,
+}
+
+/// Configuration read from `[package.metadata.swiftpkg]`. Explicit `Builder` calls take precedence.
+///
+/// ```toml
+/// [package.metadata.swiftpkg]
+/// swift-package-name = "GixGuardKit"
+/// output = "../ios/Packages"
+/// platforms = ["ios", "ios_sim", "osx"]
+/// features = ["ffi"]
+/// minimum-versions = { ios = "15.0", osx = "12.0" }
+/// udl = ["src/**/*.udl"]
+/// udl-exclude = ["src/internal/*.udl"]
+///
+/// [[package.metadata.swiftpkg.variants]]
+/// name = "release"
+/// profile = "release-lto"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct PackageConfig {
+    pub(crate) swift_package_name: Option<String>,
+    /// Directory the Swift packages are written to, relative to the crate's directory.
+    pub(crate) output: Option<PathBuf>,
+    pub(crate) platforms: Option<Vec<Platform>>,
+    /// The variants of the table, or the default ones when only `features` are set.
+    pub(crate) variants: Option<Vec<BuildVariant>>,
+    pub(crate) minimum_versions: MinimumVersions,
+    pub(crate) udl: UdlSources,
+}
+
+impl PackageConfig {
+    /// Reads the `swiftpkg` key of the package's metadata, if there is one.
+    pub(crate) fn from_metadata(metadata: &serde_json::Value, manifest_path: &Path) -> Result<Self> {
+        let Some(table) = metadata.get("swiftpkg") else {
+            return Ok(PackageConfig::default());
+        };
+        let invalid = |message: String| Error::Configuration {
+            message: format!("Invalid [package.metadata.swiftpkg] in {:?}: {}", manifest_path, message),
+        };
+        let raw: RawConfig = serde_json::from_value(table.clone()).map_err(|e| invalid(e.to_string()))?;
+
+        let platforms = raw.platforms.as_ref()
+            .map(|f| f.iter().map(|p| p.parse::<Platform>()).collect::<Result<Vec<Platform>>>())
+            .transpose()
+            .map_err(|e| invalid(e.to_string()))?;
+
+        let mut minimum_versions = MinimumVersions::default();
+        for (platform, version) in &raw.minimum_versions {
+            let platform = platform.parse::<Platform>().map_err(|e| invalid(e.to_string()))?;
+            minimum_versions.set(platform, version.clone());
+        }
+
+        let variants = match raw.variants {
+            Some(variants) => Some(variants.into_iter()
+                .map(|f| PackageConfig::variant(f, &raw.features, raw.no_default_features))
+                .collect()),
+            None if !raw.features.is_empty() || raw.no_default_features => Some(BuildVariant::defaults().into_iter()
+                .map(|f| f.with_cargo_options(PackageConfig::cargo_options(&raw.features, raw.no_default_features)))
+                .collect()),
+            None => None,
+        };
+
+        Ok(PackageConfig {
+            swift_package_name: raw.swift_package_name,
+            output: raw.output,
+            platforms,
+            variants,
+            minimum_versions,
+            udl: UdlSources {
+                include: raw.udl,
+                exclude: raw.udl_exclude,
+            },
+        })
+    }
+
+    /// `debug` and `release` start from `BuildVariant::debug` and `BuildVariant::release`.
+    /// The features of the table apply to every variant, in addition to the variant's own.
+    fn variant(raw: RawVariant, features: &[String], no_default_features: bool) -> BuildVariant {
+        let mut variant = match raw.name.as_str() {
+            "debug" => BuildVariant::debug(),
+            "release" => BuildVariant::release(),
+            name => BuildVariant::new(name),
+        };
+        if let Some(suffix) = raw.suffix {
+            variant = variant.with_suffix(suffix);
+        }
+        let mut options = PackageConfig::cargo_options(features, no_default_features || raw.no_default_features)
+            .with_features(raw.features)
+            .with_extra_args(raw.extra_args);
+        if let Some(profile) = raw.profile {
+            options = options.with_cargo_profile(profile);
+        }
+        variant.with_cargo_options(options)
+    }
+
+    fn cargo_options(features: &[String], no_default_features: bool) -> CargoOptions {
+        let options = CargoOptions::new().with_features(features.iter().cloned());
+        match no_default_features {
+            true => options.no_default_features(),
+            false => options,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use serde_json::json;
+    use super::*;
+    use crate::{Builder, RecordingCommandRunner};
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn reads_the_swiftpkg_table() {
+        let metadata = json!({
+            "swiftpkg": {
+                "swift-package-name": "GixGuardKit",
+                "output": "../ios/Packages",
+                "platforms": ["ios", "ios_simulator"],
+                "features": ["ffi"],
+                "minimum-versions": { "ios": "15.0" },
+                "udl": ["src/**/*.udl"],
+                "variants": [
+                    { "name": "release", "profile": "release-lto" },
+                    { "name": "staging", "suffix": "_qa", "features": ["staging"] },
+                ],
+            }
+        });
+        let config = PackageConfig::from_metadata(&metadata, Path::new("Cargo.toml")).unwrap();
+        assert_eq!(config.swift_package_name.as_deref(), Some("GixGuardKit"));
+        assert_eq!(config.platforms, Some(vec![Platform::Ios, Platform::IosSimulator]));
+        assert_eq!(config.minimum_versions.version(Platform::IosSimulator), "15.0");
+        assert_eq!(config.udl.include, vec!["src/**/*.udl"]);
+
+        let variants = config.variants.unwrap();
+        assert_eq!(variants[0].suffix, "");
+        assert_eq!(variants[0].cargo.cargo_profile(), "release-lto");
+        assert_eq!(variants[0].cargo.features, vec!["ffi"]);
+        assert_eq!(variants[1].suffix, "_qa");
+        assert_eq!(variants[1].cargo.features, vec!["ffi", "staging"]);
+    }
+
+    #[test]
+    fn applies_features_to_the_default_variants() {
+        let metadata = json!({ "swiftpkg": { "features": ["ffi"] } });
+        let variants = PackageConfig::from_metadata(&metadata, Path::new("Cargo.toml")).unwrap().variants.unwrap();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[1].cargo.cargo_profile(), "release");
+        assert!(variants.iter().all(|f| f.cargo.features == vec!["ffi"]));
+
+        assert_eq!(PackageConfig::from_metadata(&json!(null), Path::new("Cargo.toml")).unwrap(), PackageConfig::default());
+        assert!(PackageConfig::from_metadata(&json!({ "swiftpkg": { "platform": ["ios"] } }), Path::new("Cargo.toml")).is_err());
+        assert!(PackageConfig::from_metadata(&json!({ "swiftpkg": { "platforms": ["android"] } }), Path::new("Cargo.toml")).is_err());
+    }
+
+    #[test]
+    fn reads_configuration_from_cargo_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let manifest = fs::read_to_string(dir.path().join("Cargo.toml")).unwrap();
+        fs::write(dir.path().join("Cargo.toml"), manifest.replace("[lib]", r#"[package.metadata.swiftpkg]
+swift-package-name = "Guard"
+output = "apple"
+platforms = ["ios"]
+features = ["ffi"]
+minimum-versions = { ios = "15.0", osx = "12.0" }
+
+[lib]"#)).unwrap();
+        let builder = || Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new());
+
+        let plan = builder().plan().unwrap();
+        assert_eq!(plan.package.swift_package_name, "Guard");
+        assert_eq!(plan.package.swift_package_path, dir.path().join("apple").join("Guard"));
+        assert_eq!(plan.targets.iter().map(|f| f.triple.as_str()).collect::<Vec<&str>>(),
+                   vec!["aarch64-apple-ios", "aarch64-apple-ios"]);
+        assert_eq!(plan.targets[0].envs[0], ("IPHONEOS_DEPLOYMENT_TARGET".to_string(), "15.0".to_string()));
+        assert!(plan.commands.iter().any(|f| f.to_string().contains("--release --features ffi --target aarch64-apple-ios ")));
+
+        let plan = builder()
+            .with_swift_package_name("GuardKit".to_string())
+            .with_platforms([Platform::MacOs])
+            .with_variants([BuildVariant::release()])
+            .with_minimum_version(Platform::MacOs, "13.0")
+            .plan()
+            .unwrap();
+        assert_eq!(plan.package.swift_package_path, dir.path().join("apple").join("GuardKit"));
+        assert_eq!(plan.targets.len(), 2);
+        assert!(plan.targets.iter().all(|f| f.envs[0] == ("MACOSX_DEPLOYMENT_TARGET".to_string(), "13.0".to_string())));
+        assert!(!plan.commands.iter().any(|f| f.args.contains(&"ffi".to_string())));
+    }
+
+    #[test]
+    fn selects_configured_variants_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let manifest = fs::read_to_string(dir.path().join("Cargo.toml")).unwrap();
+        fs::write(dir.path().join("Cargo.toml"), manifest.replace("[lib]", r#"[package.metadata.swiftpkg]
+platforms = ["ios"]
+
+[[package.metadata.swiftpkg.variants]]
+name = "release"
+profile = "release-lto"
+
+[[package.metadata.swiftpkg.variants]]
+name = "staging"
+profile = "release"
+features = ["staging-endpoints"]
+
+[lib]"#)).unwrap();
+        let builder = || Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new());
+
+        let plan = builder()
+            .with_variant_names(["staging", "debug"])
+            .with_features(["logging"])
+            .with_no_default_features(true)
+            .plan()
+            .unwrap();
+        assert_eq!(plan.targets.iter().map(|f| f.variant.as_str()).collect::<Vec<&str>>(), vec!["staging", "debug"]);
+        let commands = plan.commands.iter().map(|f| f.to_string()).collect::<Vec<String>>();
+        assert!(commands.iter().any(|f| f.contains(" --release --no-default-features --features staging-endpoints,logging ")));
+        assert!(commands.iter().any(|f| f.contains(" --lib --no-default-features --features logging ")));
+        assert!(plan.xc_frameworks[0].ends_with("FixtureKit_staging.xcframework"));
+
+        let plan = builder().with_features(["logging"]).plan().unwrap();
+        assert_eq!(plan.targets.iter().map(|f| f.variant.as_str()).collect::<Vec<&str>>(), vec!["release", "staging"]);
+        assert!(plan.commands.iter().any(|f| f.to_string().contains(" --profile release-lto --features logging ")));
+
+        let error = builder().with_variant_names(["qa"]).plan().unwrap_err();
+        assert!(matches!(error, Error::Configuration { .. }));
+        assert!(error.to_string().contains("release, staging"));
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use super::bindgen::Bindgen;
+use super::build_context::BuildContext;
+use super::command_runner::ProcessCommand;
+use super::platform::{Platform, Slice};
+use super::toolchain::Toolchain;
+use super::xc_framework_generator::XCFrameworkBuilder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    /// Generation may still work, e.g. rustup is missing but the targets are installed.
+    Warn,
+    Fail,
+}
+
+/// The outcome of one check of `Builder::doctor`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Check {
+    /// What was checked, e.g. `xcodebuild`.
+    pub name: String,
+    pub status: CheckStatus,
+    /// What was found, e.g. the version of a tool or why it is unusable.
+    pub detail: String,
+    /// How to fix a warning or failure.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    pub(crate) fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.to_string(),
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    pub(crate) fn warn(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            status: CheckStatus::Warn,
+            hint: Some(hint.into()),
+            ..Check::pass(name, detail)
+        }
+    }
+
+    pub(crate) fn fail(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            status: CheckStatus::Fail,
+            hint: Some(hint.into()),
+            ..Check::pass(name, detail)
+        }
+    }
+}
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let status = match self.status {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+        };
+        write!(f, "[{}] {}: {}", status, self.name, self.detail)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every check of `Builder::doctor`, in the order the pipeline needs them.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostics {
+    pub checks: Vec<Check>,
+}
+
+impl Diagnostics {
+    pub fn has_failures(&self) -> bool {
+        self.checks.iter().any(|f| f.status == CheckStatus::Fail)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "{}", check)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks each tool and directory the pipeline needs, without changing anything.
+pub(crate) struct Doctor {
+    context: BuildContext,
+    toolchain: Toolchain,
+    slices: Vec<Slice>,
+    offline: bool,
+    output_dirs: Vec<PathBuf>,
+}
+
+impl Doctor {
+    pub(crate) fn new(context: BuildContext, toolchain: Toolchain) -> Self {
+        Doctor {
+            context,
+            toolchain,
+            slices: Platform::DEFAULT.into_iter().map(Slice::from).collect(),
+            offline: false,
+            output_dirs: vec![],
+        }
+    }
+
+    pub(crate) fn with_slices(mut self, slices: Vec<Slice>) -> Self {
+        self.slices = slices;
+        self
+    }
+
+    pub(crate) fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Directories the run writes to, each checked for write access.
+    pub(crate) fn with_output_dirs(mut self, output_dirs: Vec<PathBuf>) -> Self {
+        self.output_dirs = output_dirs;
+        self
+    }
+
+    pub(crate) fn run(&self) -> Vec<Check> {
+        let mut checks = vec![
+            self.check_cargo(),
+            self.check_rustup(),
+            self.check_targets(),
+            self.check_uniffi_bindgen(),
+            self.check_tool("xcodebuild", Ok(ProcessCommand::new("xcodebuild")), "-version",
+                            "Install Xcode and select it with `sudo xcode-select -s /Applications/Xcode.app`."),
+            self.check_tool("lipo", Ok(ProcessCommand::new("xcrun").arg("--find")), "lipo",
+                            "Install the Xcode command line tools with `xcode-select --install`."),
+            Check::pass("shell", "Not needed: every command is spawned directly, without a shell."),
+        ];
+        checks.extend(self.output_dirs.iter().map(|f| Doctor::check_writable(f)));
+        checks
+    }
+
+    fn check_uniffi_bindgen(&self) -> Check {
+        let hint = format!("{} Or enable the `bindgen` feature.", super::bindgen::install_hint(None));
+        match Bindgen::resolve() {
+            Ok(Bindgen::Binary(path)) => self.check_tool("uniffi-bindgen", Ok(ProcessCommand::new(path)), "--version", &hint),
+            #[cfg(feature = "bindgen")]
+            Ok(Bindgen::Linked) => Check::pass("uniffi-bindgen", "Linked in through the `bindgen` feature"),
+            Err(e) => Check::fail("uniffi-bindgen", e.to_string(), &hint),
+        }
+    }
+
+    /// Runs `command arg` and reports the first line of its output.
+    fn check_tool(&self, name: &str, command: crate::Result<ProcessCommand>, arg: &str, hint: &str) -> Check {
+        match command.and_then(|f| self.context.query(&f.arg(arg))) {
+            Ok(output) => Check::pass(name, output.stdout.lines().next().unwrap_or_default().trim()),
+            Err(e) => Check::fail(name, e.to_string(), hint),
+        }
+    }
+
+    fn check_cargo(&self) -> Check {
+        let cargo = match self.toolchain.cargo(false) {
+            Ok(cargo) => cargo,
+            Err(e) => return Check::fail("cargo", e.to_string(), "Install Rust from https://rustup.rs or set CARGO_HOME."),
+        };
+        let program = cargo.program.clone();
+        match self.context.query(&cargo.arg("--version")) {
+            Ok(output) => Check::pass("cargo", format!("{} ({})", program, output.stdout.trim())),
+            Err(e) => Check::fail("cargo", e.to_string(),
+                                  "Install Rust from https://rustup.rs, or select a cargo with `Builder::with_cargo`."),
+        }
+    }
+
+    fn check_rustup(&self) -> Check {
+        match self.context.query(&ProcessCommand::new("rustup").arg("--version")) {
+            Ok(output) => Check::pass("rustup", output.stdout.lines().next().unwrap_or_default().trim()),
+            Err(e) if self.offline || !self.toolchain.is_rustup_managed() => Check::warn(
+                "rustup", e.to_string(),
+                "Only needed to install missing targets, which offline builds and explicit cargo binaries don't do."),
+            Err(e) => Check::fail("rustup", e.to_string(),
+                                  "Install rustup from https://rustup.rs, or build with `Builder::with_offline(true)`."),
+        }
+    }
+
+    fn check_targets(&self) -> Check {
+        let mut triples: Vec<&String> = vec![];
+        for triple in self.slices.iter().flat_map(|f| f.triples.iter()) {
+            if !Platform::requires_build_std(triple) && !triples.contains(&triple) {
+                triples.push(triple);
+            }
+        }
+
+        let verify_sysroot = self.offline || !self.toolchain.is_rustup_managed();
+        let installed = match verify_sysroot {
+            true => self.rustc_sysroot().map(|sysroot| triples.iter()
+                .filter(|f| XCFrameworkBuilder::sysroot_has_std(&sysroot, f))
+                .map(|f| f.to_string())
+                .collect::<Vec<String>>()),
+            false => self.context.query(&self.rustup(["target", "list", "--installed"]))
+                .map(|f| f.stdout.lines().map(|l| l.trim().to_string()).collect()),
+        };
+        let installed = match installed {
+            Ok(installed) => installed,
+            Err(e) => return Check::warn("targets", format!("Unable to list the installed targets: {}", e),
+                                         "Make sure the toolchain is installed and rerun the check."),
+        };
+
+        let missing = triples.iter()
+            .filter(|f| !installed.contains(f))
+            .map(|f| f.as_str())
+            .collect::<Vec<&str>>();
+        if missing.is_empty() {
+            return Check::pass("targets", format!("{} installed", triples.iter()
+                .map(|f| f.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")));
+        }
+        let detail = format!("Missing {}", missing.join(", "));
+        let add = self.rustup(["target", "add"]).args(&missing).to_string();
+        match verify_sysroot {
+            true => Check::fail("targets", detail, format!("Install them with `{}`.", add)),
+            false => Check::warn("targets", detail, format!("They are installed on the first run, or with `{}`.", add)),
+        }
+    }
+
+    fn rustc_sysroot(&self) -> crate::Result<PathBuf> {
+        let output = self.context.query(&self.toolchain.rustc(false)?.args(["--print", "sysroot"]))?;
+        Ok(PathBuf::from(output.stdout.trim()))
+    }
+
+    fn rustup<I, S>(&self, args: I) -> ProcessCommand
+        where
+            I: IntoIterator<Item = S>,
+            S: AsRef<std::ffi::OsStr> {
+        let command = ProcessCommand::new("rustup").args(args);
+        match self.toolchain.rustup_toolchain() {
+            Some(toolchain) => command.args(["--toolchain", toolchain]),
+            None => command,
+        }
+    }
+
+    /// Creates and removes a file in the directory, or in its closest existing parent it would be created in.
+    fn check_writable(dir: &Path) -> Check {
+        let name = "output directory";
+        let Some(existing) = dir.ancestors().find(|f| f.is_dir()) else {
+            return Check::fail(name, format!("{} has no existing parent directory", dir.display()),
+                               "Check the configured output and build directories.");
+        };
+        let probe = existing.join(format!(".swiftpkg-doctor-{}", std::process::id()));
+        match std::fs::write(&probe, "").and_then(|_| std::fs::remove_file(&probe)) {
+            Ok(_) => Check::pass(name, format!("{} is writable", dir.display())),
+            Err(e) => Check::fail(name, format!("{} is not writable: {}", existing.display(), e),
+                                  "Fix its permissions, or choose another output or build directory."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Builder, CommandOutput, RecordingCommandRunner};
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn doctor_reports_each_tool_with_hints() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let runner = RecordingCommandRunner::new()
+            .respond("rustup target list", CommandOutput::success("aarch64-apple-ios\naarch64-apple-darwin\n"))
+            .respond("rustup --version", CommandOutput::success("rustup 1.28.1\n"))
+            .respond("xcodebuild", CommandOutput::failure(1, "xcode-select: error: tool 'xcodebuild' requires Xcode"))
+            .respond("--version", CommandOutput::success("cargo 1.90.0\n"));
+
+        let diagnostics = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner)
+            .doctor();
+        let check = |name: &str| diagnostics.checks.iter().find(|f| f.name == name).unwrap().clone();
+
+        assert_eq!(check("crate").status, CheckStatus::Pass);
+        assert_eq!(check("rustup").detail, "rustup 1.28.1");
+        let targets = check("targets");
+        assert_eq!(targets.status, CheckStatus::Warn);
+        assert!(targets.detail.contains("aarch64-apple-ios-sim"));
+        assert!(targets.hint.unwrap().contains("rustup target add"));
+        let pinned = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new()
+                .respond("rustup target list", CommandOutput::success("aarch64-apple-ios\n")))
+            .with_toolchain("nightly-2026-09-01")
+            .with_platforms([Platform::Ios, Platform::IosSimulator])
+            .doctor();
+        let targets = pinned.checks.iter().find(|f| f.name == "targets").unwrap();
+        assert!(targets.hint.as_ref().unwrap().contains("rustup target add --toolchain nightly-2026-09-01 aarch64-apple-ios-sim"));
+        let xcodebuild = check("xcodebuild");
+        assert_eq!(xcodebuild.status, CheckStatus::Fail);
+        assert!(xcodebuild.hint.unwrap().contains("xcode-select"));
+        assert!(diagnostics.has_failures());
+        assert_eq!(diagnostics.checks.iter().filter(|f| f.name == "output directory" && f.status == CheckStatus::Pass).count(), 2);
+
+        let parsed: Diagnostics = serde_json::from_str(&diagnostics.to_json()).unwrap();
+        assert_eq!(parsed, diagnostics);
+        assert!(Builder::new().with_crate_manifest_path(dir.path().join("Missing.toml"))
+            .with_command_runner(RecordingCommandRunner::new())
+            .doctor()
+            .checks[0].status == CheckStatus::Fail);
+    }
+}
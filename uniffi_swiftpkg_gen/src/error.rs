@@ -0,0 +1,165 @@
+use std::fmt::Formatter;
+use std::io;
+use std::path::PathBuf;
+
+/// Every failure the generator can report instead of terminating the calling process.
+#[derive(Debug)]
+pub enum Error {
+    /// `cargo metadata` could not be executed or parsed, or the crate manifest could not be located.
+    Metadata {
+        manifest_path: PathBuf,
+        message: String,
+    },
+    /// The package name passed to the `Builder` is not part of the manifest.
+    PackageNotFound {
+        name: String,
+        manifest_path: PathBuf,
+    },
+    /// The library name passed to the `Builder` does not match any target of the package.
+    LibraryNotFound {
+        name: String,
+        manifest_path: PathBuf,
+    },
+    /// The package has no target an xcframework could be built from.
+    InvalidTarget {
+        package: String,
+        manifest_path: PathBuf,
+        reason: String,
+    },
+    /// A UDL file or pattern passed to the `Builder` or the configuration matches no file.
+    NoUdlFiles {
+        search_path: PathBuf,
+    },
+    /// The options passed to the `Builder` can't be built from.
+    Configuration {
+        message: String,
+    },
+    /// The Rust toolchain is not usable, e.g. a required target could not be installed.
+    Toolchain {
+        message: String,
+    },
+    /// The standard library of these targets is missing from the toolchain's sysroot
+    /// and installing targets is disabled through `Builder::with_offline`.
+    MissingTargets {
+        triples: Vec<String>,
+        sysroot: PathBuf,
+        /// The rustup toolchain the targets are installed into, when one is selected.
+        toolchain: Option<String>,
+    },
+    /// The uniffi_bindgen library, enabled by the `bindgen` feature, failed to generate the bindings
+    /// of a UDL file or, in library mode, of a compiled library.
+    Bindgen {
+        input: PathBuf,
+        message: String,
+    },
+    /// The `uniffi-bindgen` binary could not be run, most likely because it is not installed.
+    BindgenNotFound {
+        path: PathBuf,
+        message: String,
+        /// How to install a binary matching the crate's uniffi version.
+        install_hint: String,
+    },
+    /// The bindgen generating the Swift bindings belongs to a uniffi release that is incompatible with
+    /// the uniffi runtime the crate resolves to. The generated Swift would fail its checksum checks at runtime.
+    UniffiVersionMismatch {
+        uniffi_version: String,
+        bindgen: String,
+        bindgen_version: String,
+    },
+    /// A filesystem operation failed.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// An external command could not be spawned or exited unsuccessfully.
+    Command {
+        command: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+}
+
+impl Error {
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Metadata { manifest_path, message } => {
+                write!(f, "Can't parse cargo metadata in {:?} because: {}", manifest_path, message)
+            }
+            Error::PackageNotFound { name, manifest_path } => {
+                write!(f, "Specified package name '{}' not found in your Cargo.toml file at: {:?}",
+                       name, manifest_path)
+            }
+            Error::LibraryNotFound { name, manifest_path } => {
+                write!(f, "No library named '{}' found in all targets of {:?}. \
+                Check provided library name and try again.", name, manifest_path)
+            }
+            Error::InvalidTarget { package, manifest_path, reason } => {
+                write!(f, "Invalid target in cargo package.name: '{}' in file: {:?}. {}",
+                       package, manifest_path, reason)
+            }
+            Error::NoUdlFiles { search_path } => {
+                write!(f, "No UDL files match {:?}. \
+                Refer to Rust UniFFI: https://mozilla.github.io/uniffi-rs/udl_file_spec.html", search_path)
+            }
+            Error::Configuration { message } => {
+                write!(f, "Invalid configuration: {}", message)
+            }
+            Error::Toolchain { message } => {
+                write!(f, "Rust toolchain error: {}", message)
+            }
+            Error::MissingTargets { triples, sysroot, toolchain } => {
+                write!(f, "Rust targets not installed in {:?}: {}. Install them with `rustup target add {}",
+                       sysroot, triples.join(", "), triples.join(" "))?;
+                if let Some(toolchain) = toolchain {
+                    write!(f, " --toolchain {}", toolchain)?;
+                }
+                f.write_str("`.")
+            }
+            Error::Bindgen { input, message } => {
+                write!(f, "Failed to generate the Swift bindings of {:?}: {}", input, message)
+            }
+            Error::BindgenNotFound { path, message, install_hint } => {
+                write!(f, "Unable to run uniffi-bindgen at {:?}: {}. {}", path, message, install_hint)
+            }
+            Error::UniffiVersionMismatch { uniffi_version, bindgen, bindgen_version } => {
+                write!(f, "The crate uses uniffi {}, but {} is version {}. Bindings of another uniffi release \
+                compile but fail their contract checks at runtime. Align the crate's uniffi dependency, or install \
+                a matching uniffi-bindgen. {}",
+                       uniffi_version, bindgen, bindgen_version, super::bindgen::install_hint(Some(uniffi_version)))
+            }
+            Error::Io { path, source } => {
+                write!(f, "I/O error at {:?}: {}", path, source)
+            }
+            Error::Command { command, status, stderr } => {
+                match status {
+                    Some(code) => write!(f, "Command `{}` failed with exit code {}", command, code)?,
+                    None => write!(f, "Command `{}` failed", command)?,
+                }
+                if !stderr.trim().is_empty() {
+                    write!(f, ":\n{}", stderr.trim_end())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
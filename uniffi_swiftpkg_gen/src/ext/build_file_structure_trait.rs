@@ -1,7 +1,19 @@
+use std::path::{Path, PathBuf};
+use crate::error::Error;
 
 pub(crate) trait BuildFileStructure {
-    fn create_dir_if_not_exist(&self, message: &str);
-    fn write_file(self, content: String, overwrite: bool) -> Result<(), std::io::Error>;
+    fn create_dir_if_not_exist(&self) -> Result<(), Error>;
+    fn write_file(self, content: String, overwrite: bool) -> Result<(), Error>;
+    /// Removes the file or directory tree, if it exists.
+    fn remove_if_exist(&self) -> Result<(), Error>;
+    /// Copies the file or directory tree to `destination`, replacing whatever is there.
+    fn copy_recursively_to(&self, destination: &Path) -> Result<(), Error>;
+    /// Copies everything inside this directory into the `destination` directory.
+    fn copy_dir_contents_to(&self, destination: &Path) -> Result<(), Error>;
+    /// Renames to `destination`, falling back to copy and remove when crossing filesystems.
+    fn move_to(&self, destination: &Path) -> Result<(), Error>;
+    /// Files inside this directory matching the glob `pattern`, sorted by path.
+    fn glob_files(&self, pattern: &str) -> Result<Vec<PathBuf>, Error>;
 }
 
 
@@ -11,4 +23,4 @@ pub(crate) trait StringExtension {
 
 pub(crate) trait CommandOutputTrait {
     fn utf8_string(&self) -> Vec<String>;
-}
\ No newline at end of file
+}
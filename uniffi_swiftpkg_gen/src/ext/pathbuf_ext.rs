@@ -1,25 +1,144 @@
-use std::path::{ PathBuf };
+use std::path::{ Path, PathBuf };
 use super::build_file_structure_trait::*;
+use crate::error::Error;
 use std::fs::{ create_dir_all, File };
 use std::io::Write;
 
 impl BuildFileStructure for PathBuf {
-    fn create_dir_if_not_exist(&self, message: &str) {
+    fn create_dir_if_not_exist(&self) -> Result<(), Error> {
         if !self.exists() {
             create_dir_all(self)
-                .unwrap_or_else(|_| panic!("{}.\nPath: {:?}\n", message, self));
+                .map_err(|e| Error::io(self, e))?;
         }
+        Ok(())
     }
 
-    fn write_file(self, content: String, overwrite: bool) -> Result<(), std::io::Error> {
+    fn write_file(self, content: String, overwrite: bool) -> Result<(), Error> {
         if self.exists() && !overwrite {
             Ok(())
         } else {
-            let file = File::create(self.as_path());
-            match file {
-                Ok(mut f) => f.write_all(content.as_bytes()),
-                Err(e) => Err(e)
+            File::create(self.as_path())
+                .and_then(|mut f| f.write_all(content.as_bytes()))
+                .map_err(|e| Error::io(self, e))
+        }
+    }
+
+    fn remove_if_exist(&self) -> Result<(), Error> {
+        let metadata = match std::fs::symlink_metadata(self) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Error::io(self, e)),
+        };
+        if metadata.is_dir() {
+            std::fs::remove_dir_all(self)
+        } else {
+            std::fs::remove_file(self)
+        }.map_err(|e| Error::io(self, e))
+    }
+
+    fn copy_recursively_to(&self, destination: &Path) -> Result<(), Error> {
+        destination.to_path_buf().remove_if_exist()?;
+        copy_entry(self, destination)
+    }
+
+    fn copy_dir_contents_to(&self, destination: &Path) -> Result<(), Error> {
+        destination.to_path_buf().create_dir_if_not_exist()?;
+        for entry in std::fs::read_dir(self).map_err(|e| Error::io(self, e))? {
+            let entry = entry.map_err(|e| Error::io(self, e))?;
+            entry.path().copy_recursively_to(&destination.join(entry.file_name()))?;
+        }
+        Ok(())
+    }
+
+    fn move_to(&self, destination: &Path) -> Result<(), Error> {
+        match std::fs::rename(self, destination) {
+            Ok(()) => Ok(()),
+            // Renaming only works within one filesystem.
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                self.copy_recursively_to(destination)?;
+                self.remove_if_exist()
+            }
+            Err(e) => Err(Error::io(self, e)),
+        }
+    }
+
+    fn glob_files(&self, pattern: &str) -> Result<Vec<PathBuf>, Error> {
+        let full_pattern = format!("{}/{}",
+                                   glob::Pattern::escape(&self.to_string_lossy()), pattern);
+        let paths = glob::glob(&full_pattern)
+            .map_err(|e| Error::io(self, std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))?;
+        let mut files = vec![];
+        for path in paths {
+            let path = path.map_err(|e| Error::io(e.path().to_path_buf(), e.into()))?;
+            if path.is_file() {
+                files.push(path);
             }
         }
+        files.sort();
+        Ok(files)
+    }
+}
+
+fn copy_entry(source: &Path, destination: &Path) -> Result<(), Error> {
+    let metadata = std::fs::symlink_metadata(source).map_err(|e| Error::io(source, e))?;
+    if metadata.is_symlink() {
+        // Framework bundles use relative symlinks, keep them as links.
+        let target = std::fs::read_link(source).map_err(|e| Error::io(source, e))?;
+        return symlink(&target, destination);
+    }
+    if metadata.is_dir() {
+        create_dir_all(destination).map_err(|e| Error::io(destination, e))?;
+        for entry in std::fs::read_dir(source).map_err(|e| Error::io(source, e))? {
+            let entry = entry.map_err(|e| Error::io(source, e))?;
+            copy_entry(&entry.path(), &destination.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        std::fs::copy(source, destination)
+            .map(|_| ())
+            .map_err(|e| Error::io(destination, e))
+    }
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> Result<(), Error> {
+    std::os::unix::fs::symlink(target, link).map_err(|e| Error::io(link, e))
+}
+
+#[cfg(not(unix))]
+fn symlink(target: &Path, link: &Path) -> Result<(), Error> {
+    let source = link.parent().map(|f| f.join(target)).unwrap_or_else(|| target.to_path_buf());
+    copy_entry(&source, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::BuildFileStructure;
+
+    #[test]
+    fn copies_moves_and_globs_paths_with_spaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("bindgen out");
+        fs::create_dir_all(source.join("nested dir")).unwrap();
+        fs::write(source.join("MyKitFFI.modulemap"), "module MyKitFFI {}").unwrap();
+        fs::write(source.join("MyKit.swift"), "").unwrap();
+        fs::write(source.join("nested dir").join("MyKitFFI.h"), "").unwrap();
+
+        assert_eq!(source.glob_files("*FFI.modulemap").unwrap(), vec![source.join("MyKitFFI.modulemap")]);
+        assert!(source.glob_files("*.xcframework").unwrap().is_empty());
+
+        let destination = dir.path().join("package [debug]");
+        source.copy_dir_contents_to(&destination).unwrap();
+        assert!(destination.join("nested dir").join("MyKitFFI.h").is_file());
+
+        let module_map = destination.join("module.modulemap");
+        destination.join("MyKitFFI.modulemap").move_to(&module_map).unwrap();
+        assert_eq!(fs::read_to_string(module_map).unwrap(), "module MyKitFFI {}");
+        assert!(!destination.join("MyKitFFI.modulemap").exists());
+
+        destination.remove_if_exist().unwrap();
+        assert!(!destination.exists());
+        dir.path().join("missing").remove_if_exist().unwrap();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// File in the build dir the fingerprints of the last run are kept in.
+const FINGERPRINTS_FILE: &str = "fingerprints.json";
+
+/// FNV-1a over the inputs of a stage, which unlike `DefaultHasher` is the same across Rust releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub(crate) fn new() -> Self {
+        Fingerprint(0xcbf29ce484222325)
+    }
+
+    /// Hashes `bytes` as they are.
+    pub(crate) fn write(self, bytes: &[u8]) -> Self {
+        Fingerprint(bytes.iter().fold(self.0, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        }))
+    }
+
+    /// Hashes one input, prefixed with its length so consecutive inputs can't run into each other.
+    pub(crate) fn add(self, input: impl AsRef<[u8]>) -> Self {
+        let input = input.as_ref();
+        self.write(&(input.len() as u64).to_le_bytes()).write(input)
+    }
+
+    /// Hashes the path and contents of a file, or only its path if it can't be read.
+    pub(crate) fn add_file(self, path: &Path) -> Self {
+        let fingerprint = self.add(path.to_string_lossy().as_bytes());
+        match std::fs::read(path) {
+            Ok(contents) => fingerprint.add(contents),
+            Err(_) => fingerprint.add("<missing>"),
+        }
+    }
+
+    pub(crate) fn value(&self) -> u64 {
+        self.0
+    }
+
+    pub(crate) fn to_hex(self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// The fingerprints of the stages of the last run, read from the build dir, and those of the current run.
+///
+/// A stage is skipped when its inputs hash to the fingerprint it had last time and its outputs still exist.
+#[derive(Debug)]
+pub(crate) struct Fingerprints {
+    path: PathBuf,
+    force: bool,
+    previous: BTreeMap<String, String>,
+    current: Mutex<BTreeMap<String, String>>,
+    unchanged: Mutex<Vec<String>>,
+}
+
+impl Fingerprints {
+    /// Reads the fingerprints of the last run in `build_dir`. With `force` every stage runs again.
+    pub(crate) fn load(build_dir: &Path, force: bool) -> Self {
+        let path = build_dir.join(FINGERPRINTS_FILE);
+        let previous = std::fs::read_to_string(&path).ok()
+            .and_then(|f| serde_json::from_str(&f).ok())
+            .unwrap_or_default();
+        Fingerprints {
+            path,
+            force,
+            previous,
+            current: Mutex::new(BTreeMap::new()),
+            unchanged: Mutex::new(vec![]),
+        }
+    }
+
+    /// Whether `stage` ran with the same `fingerprint` last time and all of its `outputs` still exist.
+    /// If so the stage is reported as unchanged and keeps its fingerprint.
+    pub(crate) fn is_fresh(&self, stage: &str, fingerprint: &str, outputs: &[&Path]) -> bool {
+        let is_fresh = !self.force
+            && self.previous.get(stage).map(|f| f == fingerprint).unwrap_or(false)
+            && outputs.iter().all(|f| f.exists());
+        if is_fresh {
+            self.record(stage, fingerprint.to_string());
+            if let Ok(mut unchanged) = self.unchanged.lock() {
+                unchanged.push(stage.to_string());
+            }
+        }
+        is_fresh
+    }
+
+    /// Remembers the fingerprint of a stage that ran, saved with the others by `to_json`.
+    pub(crate) fn record(&self, stage: &str, fingerprint: String) {
+        if let Ok(mut current) = self.current.lock() {
+            current.insert(stage.to_string(), fingerprint);
+        }
+    }
+
+    /// Fingerprint of a stage of the current run, which later stages depend on.
+    pub(crate) fn current(&self, stage: &str) -> String {
+        self.current.lock().ok()
+            .and_then(|f| f.get(stage).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Stages skipped because their inputs didn't change, in the order they were checked.
+    pub(crate) fn unchanged(&self) -> Vec<String> {
+        self.unchanged.lock().map(|f| f.clone()).unwrap_or_default()
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The fingerprints of the last run, updated with those of the current run.
+    pub(crate) fn to_json(&self) -> String {
+        let mut fingerprints = self.previous.clone();
+        if let Ok(current) = self.current.lock() {
+            fingerprints.extend(current.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        serde_json::to_string_pretty(&fingerprints).unwrap_or_default()
+    }
+}
+
+/// Variables that change how cargo compiles a target without showing on its command line.
+/// Those starting with `CARGO_PROFILE_` override the `[profile.*]` tables as well.
+const BUILD_ENV_VARS: [&str; 3] = ["RUSTFLAGS", "CARGO_ENCODED_RUSTFLAGS", "CARGO_BUILD_RUSTFLAGS"];
+
+/// The rustflags and profile overrides among `vars`, sorted so their order in the environment doesn't matter.
+pub(crate) fn build_env_vars(vars: impl IntoIterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut build_vars = vars.into_iter()
+        .filter(|(key, _)| BUILD_ENV_VARS.contains(&key.as_str()) || key.starts_with("CARGO_PROFILE_"))
+        .collect::<Vec<(String, String)>>();
+    build_vars.sort();
+    build_vars
+}
+
+/// Source files listed in a Makefile-style dep-info file written by cargo, without duplicates.
+pub(crate) fn dep_info_files(contents: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = vec![];
+    for line in contents.lines() {
+        let Some((_, deps)) = line.split_once(": ") else {
+            continue;
+        };
+        let mut file = String::new();
+        let mut chars = deps.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&' ') => {
+                    file.push(' ');
+                    chars.next();
+                }
+                ' ' => {
+                    if !file.is_empty() {
+                        files.push(PathBuf::from(std::mem::take(&mut file)));
+                    }
+                }
+                c => file.push(c),
+            }
+        }
+        if !file.is_empty() {
+            files.push(PathBuf::from(file));
+        }
+    }
+    files.sort();
+    files.dedup();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use super::*;
+
+    #[test]
+    fn parses_dep_info() {
+        let contents = "/t/libmy_crate.a: /w/src/lib.rs /w/My\\ Crate/src/ffi.rs\n\
+                        /t/libmy_crate.d: /w/src/lib.rs\n\n\
+                        /w/src/lib.rs:\n";
+        assert_eq!(dep_info_files(contents),
+                   vec![PathBuf::from("/w/My Crate/src/ffi.rs"), PathBuf::from("/w/src/lib.rs")]);
+    }
+
+    #[test]
+    fn selects_the_build_env_vars() {
+        let vars = [("RUSTFLAGS", "-Copt-level=s"), ("PATH", "/usr/bin"),
+                    ("CARGO_PROFILE_RELEASE_LTO", "true"), ("CARGO_HOME", "/c")]
+            .map(|(k, v)| (k.to_string(), v.to_string()));
+        assert_eq!(build_env_vars(vars), vec![
+            ("CARGO_PROFILE_RELEASE_LTO".to_string(), "true".to_string()),
+            ("RUSTFLAGS".to_string(), "-Copt-level=s".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn skips_stages_with_unchanged_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("libmy_crate.a");
+        fs::write(&output, "lib").unwrap();
+        let fingerprint = Fingerprint::new().add("cargo build").add_file(&dir.path().join("Cargo.lock")).to_hex();
+        assert_ne!(Fingerprint::new().add("ab").add("c"), Fingerprint::new().add("a").add("bc"));
+
+        let first = Fingerprints::load(dir.path(), false);
+        assert!(!first.is_fresh("cargo debug aarch64-apple-ios", &fingerprint, &[&output]));
+        first.record("cargo debug aarch64-apple-ios", fingerprint.clone());
+        fs::write(first.path(), first.to_json()).unwrap();
+
+        let second = Fingerprints::load(dir.path(), false);
+        assert!(second.is_fresh("cargo debug aarch64-apple-ios", &fingerprint, &[&output]));
+        assert!(!second.is_fresh("cargo debug aarch64-apple-ios", "0", &[&output]));
+        assert_eq!(second.unchanged(), vec!["cargo debug aarch64-apple-ios"]);
+        assert_eq!(second.current("cargo debug aarch64-apple-ios"), fingerprint);
+
+        assert!(!Fingerprints::load(dir.path(), true).is_fresh("cargo debug aarch64-apple-ios", &fingerprint, &[&output]));
+        fs::remove_file(&output).unwrap();
+        assert!(!Fingerprints::load(dir.path(), false).is_fresh("cargo debug aarch64-apple-ios", &fingerprint, &[&output]));
+    }
+
+    // Scripts the output of the uniffi-bindgen binary, which the `bindgen` feature replaces.
+    #[cfg(not(feature = "bindgen"))]
+    #[test]
+    fn skips_the_stages_of_a_run_with_unchanged_inputs() {
+        use std::sync::Arc;
+        use crate::{Builder, BuildVariant, Platform, RecordingCommandRunner};
+        use crate::test_fixtures::*;
+
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let generate = |force: bool| {
+            let runner = Arc::new(RecordingCommandRunner::new()
+                .respond_with("uniffi-bindgen", fake_uniffi_bindgen)
+                .respond_with("build --locked", fake_cargo_build)
+                .respond_with("lipo -create", fake_lipo)
+                .respond_with("xcodebuild", fake_xcodebuild));
+            let report = Builder::new()
+                .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+                .with_command_runner(runner.clone())
+                .with_platforms([Platform::Ios])
+                .with_variants([BuildVariant::release()])
+                .with_force(force)
+                .try_generate()
+                .unwrap();
+            let programs = runner.calls().into_iter()
+                .filter(|f| f.args.contains(&"build".to_string()) || ["lipo", "xcodebuild"].contains(&f.program.as_str())
+                    || (f.program.ends_with("uniffi-bindgen") && f.args[0] == "generate"))
+                .count();
+            (report.unchanged, programs)
+        };
+
+        assert_eq!(generate(false), (vec![], 4));
+        assert_eq!(generate(false), (vec!["uniffi-bindgen".to_string(), "cargo release aarch64-apple-ios".to_string(),
+                                          "lipo release ios".to_string(), "xcframework release".to_string()], 0));
+
+        fs::write(dir.path().join("src").join("lib.rs"), "pub fn answer() -> u32 { 42 }\n").unwrap();
+        assert_eq!(generate(false), (vec!["uniffi-bindgen".to_string()], 3));
+        let manifest = fs::read_to_string(dir.path().join("Cargo.toml")).unwrap();
+        fs::write(dir.path().join("Cargo.toml"), format!("{}\n[profile.release]\nopt-level = \"s\"\n", manifest)).unwrap();
+        assert_eq!(generate(false), (vec!["uniffi-bindgen".to_string()], 3));
+        assert_eq!(generate(true), (vec![], 4));
+    }
+}
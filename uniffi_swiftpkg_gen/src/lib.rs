@@ -1,45 +1,127 @@
 use std::path::{Path, PathBuf};
 
 mod cargo_package;
-mod xcgenerator;
 mod xc_framework_generator;
 mod swift_package_item;
 mod ext;
 mod command_builder;
+mod command_runner;
 mod shared_constants;
 mod types;
+mod error;
+mod report;
+mod plan;
+mod build_context;
+mod platform;
+mod toolchain;
+mod cargo_options;
+mod build_variant;
+mod target_env;
+mod fingerprint;
+mod config;
+mod doctor;
+mod bindgen;
+mod udl;
+#[cfg(test)]
+mod test_fixtures;
 
 use std::env;
+use std::process::exit;
+use std::sync::Arc;
+use colored::Colorize;
 use cargo_package::*;
 use xc_framework_generator::XCFrameworkBuilder;
 use shared_constants::*;
 use crate::swift_package_item::SwiftPackageItem;
+use crate::command_builder::CommandBuilder;
+use crate::build_context::BuildContext;
+use crate::toolchain::Toolchain;
+use crate::target_env::TargetEnvironment;
+use crate::fingerprint::Fingerprints;
+use crate::doctor::Doctor;
+use crate::ext::BuildFileStructure;
+pub use error::{Error, Result};
+pub use report::Report;
+pub use plan::{BuildPlan, FileOperation, PlannedPackage, PlannedTarget};
+pub use platform::{Platform, Slice};
+use platform::MinimumVersions;
+use udl::UdlSources;
+pub use cargo_options::CargoOptions;
+pub use build_variant::BuildVariant;
+pub use target_env::TargetSelector;
+pub use doctor::{Check, CheckStatus, Diagnostics};
+pub use command_runner::{CommandOutput, CommandRunner, ProcessCommand, RecordingCommandRunner, SystemCommandRunner};
 
 #[allow(unused)]
 pub struct Builder {
     create_path: PathBuf,
-    crate_package_name: String,
+    crate_package_name: Option<String>,
     xcode_framework_name: Option<String>,
-    xcode_framework_path: PathBuf,
+    xcode_framework_path: Option<PathBuf>,
     crate_lib_name: Option<String>,
+    command_runner: Option<Arc<dyn CommandRunner>>,
+    dry_run: bool,
+    build_dir: Option<PathBuf>,
+    slices: Option<Vec<Slice>>,
+    offline: bool,
+    toolchain: Option<Toolchain>,
+    variants: Option<Vec<BuildVariant>>,
+    variant_names: Vec<String>,
+    features: Vec<String>,
+    no_default_features: bool,
+    environment: TargetEnvironment,
+    minimum_versions: MinimumVersions,
+    jobs: usize,
+    force: bool,
+    udl_sources: UdlSources,
 }
 
 impl Builder {
     #[allow(clippy::new_without_default)]
     #[allow(unused)]
     pub fn new() -> Self {
-        let package_name = env::var("CARGO_PKG_NAME").unwrap();
-        let crate_dir = Path::new(env::var("CARGO_MANIFEST_DIR").unwrap().as_str()).to_path_buf();
+        let package_name = env::var("CARGO_PKG_NAME").ok();
+        let crate_dir = Path::new(env::var("CARGO_MANIFEST_DIR").unwrap_or_default().as_str()).to_path_buf();
         let create_path = crate_dir.join("Cargo.toml");
         Builder {
             create_path,
             crate_package_name: package_name,
             xcode_framework_name: None,
-            xcode_framework_path: crate_dir.join("xcode"),
+            xcode_framework_path: None,
             crate_lib_name: None,
+            command_runner: None,
+            dry_run: false,
+            build_dir: None,
+            slices: None,
+            offline: false,
+            toolchain: None,
+            variants: None,
+            variant_names: vec![],
+            features: vec![],
+            no_default_features: false,
+            environment: TargetEnvironment::default(),
+            minimum_versions: MinimumVersions::default(),
+            jobs: 1,
+            force: false,
+            udl_sources: UdlSources::default(),
         }
     }
 
+    /// Generates the package for the crate at `manifest_path` instead of the crate running the build script.
+    #[allow(unused)]
+    pub fn with_crate_manifest_path(mut self, manifest_path: impl Into<PathBuf>) -> Builder {
+        self.create_path = manifest_path.into();
+        self.crate_package_name = None;
+        self
+    }
+
+    /// Selects the package of a workspace, after `with_crate_manifest_path` pointed at the workspace manifest.
+    #[allow(unused)]
+    pub fn with_crate_package_name(mut self, package_name: impl Into<String>) -> Builder {
+        self.crate_package_name = Some(package_name.into());
+        self
+    }
+
     #[allow(unused)]
     pub fn with_crate_lib_name(mut self, lib_name: String) -> Builder {
         self.crate_lib_name = Some(lib_name);
@@ -54,46 +136,518 @@ impl Builder {
 
     #[allow(unused)]
     pub fn with_swift_package_build_path(mut self, build_path: String) -> Builder {
-        self.xcode_framework_path = Path::new(build_path.as_str()).to_path_buf();
+        self.xcode_framework_path = Some(Path::new(build_path.as_str()).to_path_buf());
         self
     }
 
+    /// Builds the xcframework for exactly these platforms, each with its default Rust targets.
+    ///
+    /// Defaults to `Platform::DEFAULT`.
     #[allow(unused)]
-    pub fn generate(mut self) {
-        if env::var(SKIP_UNIFFI_SWIFTPKG_GEN).unwrap_or_else(|_| "false".to_string()).to_lowercase() == "true" {
+    pub fn with_platforms(mut self, platforms: impl IntoIterator<Item = Platform>) -> Builder {
+        self.slices = Some(platforms.into_iter().map(Slice::from).collect());
+        self
+    }
+
+    /// Builds the xcframework from exactly these slices, e.g. to leave out the x86_64 simulator.
+    #[allow(unused)]
+    pub fn with_slices(mut self, slices: impl IntoIterator<Item = Slice>) -> Builder {
+        self.slices = Some(slices.into_iter().collect());
+        self
+    }
+
+    /// Generates the bindings of this UDL file, e.g. the one passed to `uniffi_build::generate_scaffolding`.
+    /// Relative paths are resolved against the crate's directory. Replaces the search of `src/*.udl`.
+    #[allow(unused)]
+    pub fn with_udl_file(mut self, path: impl AsRef<Path>) -> Builder {
+        self.udl_sources.include.push(glob::Pattern::escape(&path.as_ref().to_string_lossy()));
+        self
+    }
+
+    /// Generates the bindings of the UDL files matching the glob `pattern`, relative to the crate's directory,
+    /// e.g. `src/**/*.udl` to search `src` recursively. Replaces the search of `src/*.udl`.
+    #[allow(unused)]
+    pub fn with_udl_pattern(mut self, pattern: impl Into<String>) -> Builder {
+        self.udl_sources.include.push(pattern.into());
+        self
+    }
+
+    /// Leaves out the UDL files whose path relative to the crate's directory matches the glob `pattern`.
+    #[allow(unused)]
+    pub fn with_udl_exclude(mut self, pattern: impl Into<String>) -> Builder {
+        self.udl_sources.exclude.push(pattern.into());
+        self
+    }
+
+    /// Builds with the rustup toolchain `toolchain`, e.g. `"stable"` or `"nightly-2026-09-01"`.
+    ///
+    /// Defaults to the toolchain of the build running the build script, which honours
+    /// `rust-toolchain.toml` and `RUSTUP_TOOLCHAIN`.
+    #[allow(unused)]
+    pub fn with_toolchain(mut self, toolchain: impl Into<String>) -> Builder {
+        self.toolchain = Some(Toolchain::Channel(toolchain.into()));
+        self
+    }
+
+    /// Builds with the cargo binary at `cargo` and the `rustc` next to it, for toolchains not managed by rustup.
+    /// The targets are then verified in the sysroot of that `rustc`, as with `with_offline(true)`.
+    #[allow(unused)]
+    pub fn with_cargo(mut self, cargo: impl Into<PathBuf>) -> Builder {
+        self.toolchain = Some(Toolchain::Cargo(cargo.into()));
+        self
+    }
+
+    /// Generates one xcframework and Swift package per variant, named with the variant's suffix.
+    ///
+    /// Defaults to `BuildVariant::defaults()`, i.e. `<Package>_debug` and `<Package>`.
+    #[allow(unused)]
+    pub fn with_variants(mut self, variants: impl IntoIterator<Item = BuildVariant>) -> Builder {
+        self.variants = Some(variants.into_iter().collect());
+        self
+    }
+
+    /// Builds only the variants named `names`, out of those of `with_variants`, of the `[package.metadata.swiftpkg]`
+    /// table, or the defaults. `debug` and `release` fall back to `BuildVariant::debug` and `BuildVariant::release`
+    /// when they aren't configured; any other unknown name fails with `Error::Configuration`.
+    #[allow(unused)]
+    pub fn with_variant_names<I, S>(mut self, names: I) -> Builder
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String> {
+        self.variant_names.extend(names.into_iter().map(|f| f.into()));
+        self
+    }
+
+    /// Enables cargo `features` for every variant, in addition to the variant's own.
+    #[allow(unused)]
+    pub fn with_features<I, S>(mut self, features: I) -> Builder
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String> {
+        self.features.extend(features.into_iter().map(|f| f.into()));
+        self
+    }
+
+    /// Builds every variant with `--no-default-features`, keeping the variants' other cargo options.
+    #[allow(unused)]
+    pub fn with_no_default_features(mut self, no_default_features: bool) -> Builder {
+        self.no_default_features = no_default_features;
+        self
+    }
+
+    /// Minimum OS version of `platform` and its simulator, e.g. `"15.0"`.
+    ///
+    /// It is both the deployment target the targets are compiled for, e.g. `IPHONEOS_DEPLOYMENT_TARGET`,
+    /// and the `platforms:` entry of the generated Package.swift. See `Platform::default_minimum_version`.
+    #[allow(unused)]
+    pub fn with_minimum_version(mut self, platform: Platform, version: impl Into<String>) -> Builder {
+        self.minimum_versions.set(platform, version.into());
+        self
+    }
+
+    /// Compiles up to `jobs` targets at once instead of one after another.
+    ///
+    /// Every triple is built in its own target dir, so the builds don't wait on each other's lock.
+    /// The output of each build is printed once it finishes, prefixed with its variant and triple.
+    #[allow(unused)]
+    pub fn with_jobs(mut self, jobs: usize) -> Builder {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Runs every stage, even those whose inputs are unchanged since the last run.
+    ///
+    /// Stages are skipped when the fingerprint of their inputs, kept in the build directory, still matches:
+    /// a target's cargo build covers its command line, the toolchain version, `Cargo.lock`, the UDL files
+    /// and the sources of its last build, and lipo and the xcframework cover the stages they depend on.
+    #[allow(unused)]
+    pub fn with_force(mut self, force: bool) -> Builder {
+        self.force = force;
+        self
+    }
+
+    /// Sets `key` for the `cargo build` of the targets `selector` matches, e.g. `RUSTC_WRAPPER`
+    /// or `CARGO_TARGET_<TRIPLE>_LINKER`. Later calls override earlier ones for the same key.
+    #[allow(unused)]
+    pub fn with_target_env(mut self, selector: impl Into<TargetSelector>,
+                           key: impl Into<String>, value: impl Into<String>) -> Builder {
+        self.environment.add_env(selector.into(), key.into(), value.into());
+        self
+    }
+
+    /// Passes `flags` to rustc for the targets `selector` matches, e.g. `["-C", "target-cpu=apple-m1"]`.
+    /// Flags of every matching call are combined.
+    #[allow(unused)]
+    pub fn with_rustflags<I, S>(mut self, selector: impl Into<TargetSelector>, flags: I) -> Builder
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String> {
+        self.environment.add_rustflags(selector.into(), flags.into_iter().map(|f| f.into()).collect());
+        self
+    }
+
+    /// Only verifies that the Rust targets are present in the toolchain's sysroot, and fails with
+    /// `Error::MissingTargets` when they are not, instead of running `rustup target add`.
+    ///
+    /// For hermetic builds without network and toolchains that aren't managed by rustup.
+    #[allow(unused)]
+    pub fn with_offline(mut self, offline: bool) -> Builder {
+        self.offline = offline;
+        self
+    }
+
+    /// Directory for the per-target cargo builds, the generated bindings, the fat libraries and the xcframeworks.
+    ///
+    /// Defaults to `<target dir>/swiftpkg/<crate>-<workspace hash>`, where the target dir honours `CARGO_TARGET_DIR`.
+    #[allow(unused)]
+    pub fn with_build_dir(mut self, build_dir: impl Into<PathBuf>) -> Builder {
+        self.build_dir = Some(build_dir.into());
+        self
+    }
+
+    /// Executes every external command through `runner` instead of the default shell.
+    #[allow(unused)]
+    pub fn with_command_runner<R: CommandRunner + 'static>(mut self, runner: R) -> Builder {
+        self.command_runner = Some(Arc::new(runner));
+        self
+    }
+
+    /// Records what `try_generate` would do into `Report::plan` instead of doing it.
+    #[allow(unused)]
+    pub fn with_dry_run(mut self, dry_run: bool) -> Builder {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Resolves the crate, the target matrix and every command and file change of a run,
+    /// without building or writing anything.
+    #[allow(unused)]
+    pub fn plan(self) -> Result<BuildPlan> {
+        let report = self.run(true, true)?;
+        Ok(report.plan.unwrap_or_default())
+    }
+
+    /// Generates the Swift packages and exits the process when anything fails.
+    /// Use `try_generate` to handle failures yourself.
+    #[allow(unused)]
+    pub fn generate(self) {
+        if let Err(e) = self.try_generate() {
+            eprintln!("{} {}", "error:".red().bold(), e);
+            exit(1);
+        }
+    }
+
+    /// Generates the xcframeworks and Swift packages, returning what was produced.
+    #[allow(unused)]
+    pub fn try_generate(self) -> Result<Report> {
+        self.run_unless_skipped(true)
+    }
+
+    /// Builds the xcframeworks without writing the Swift packages.
+    #[allow(unused)]
+    pub fn try_build(self) -> Result<Report> {
+        self.run_unless_skipped(false)
+    }
+
+    /// Removes the build directory, with the intermediate libraries and xcframeworks in it.
+    /// The generated Swift packages are kept. Returns the removed directories.
+    #[allow(unused)]
+    pub fn clean(self) -> Result<Vec<PathBuf>> {
+        let cargo_package = self.cargo_package()?;
+        let build_dir = self.build_dir.clone().unwrap_or_else(|| cargo_package.default_build_dir());
+        if !build_dir.exists() {
+            return Ok(vec![]);
+        }
+        build_dir.remove_if_exist()?;
+        Ok(vec![build_dir])
+    }
+
+    /// Checks every tool and directory a run needs and how to fix what is missing,
+    /// without building or installing anything.
+    #[allow(unused)]
+    pub fn doctor(self) -> Diagnostics {
+        let cargo_package = self.cargo_package();
+        let mut checks = vec![match &cargo_package {
+            Ok(package) => Check::pass("crate", format!("{} ({})", package.crate_name, package.crate_manifest_path.display())),
+            Err(e) => Check::fail("crate", e.to_string(),
+                                  "Point `with_crate_manifest_path` or `--manifest-path` at the Cargo.toml of a staticlib crate."),
+        }];
+
+        let mut doctor = Doctor::new(BuildContext::new(self.command_builder()), self.toolchain.clone().unwrap_or_else(Toolchain::from_env))
+            .with_offline(self.offline);
+        if let Ok(package) = &cargo_package {
+            let build_dir = self.build_dir.clone().unwrap_or_else(|| package.default_build_dir());
+            doctor = doctor.with_output_dirs(vec![package.swift_package_path.clone(), build_dir]);
+            if let Some(slices) = self.slices(&package.config) {
+                doctor = doctor.with_slices(slices);
+            }
+        } else if let Some(slices) = self.slices.clone() {
+            doctor = doctor.with_slices(slices);
+        }
+        checks.extend(doctor.run());
+        Diagnostics { checks }
+    }
+
+    fn command_builder(&self) -> CommandBuilder {
+        match &self.command_runner {
+            Some(runner) => CommandBuilder::with_runner(runner.clone()),
+            None => CommandBuilder::new(),
+        }
+    }
+
+    /// The slices set on the builder, or else the platforms of the `[package.metadata.swiftpkg]` table.
+    fn slices(&self, config: &config::PackageConfig) -> Option<Vec<Slice>> {
+        self.slices.clone()
+            .or_else(|| config.platforms.clone().map(|f| f.into_iter().map(Slice::from).collect()))
+    }
+
+    /// The variants set on the builder, or else those of the `[package.metadata.swiftpkg]` table, or the defaults,
+    /// narrowed to `with_variant_names` and with the features of `with_features` added.
+    fn variants(&self, config: &config::PackageConfig) -> Result<Vec<BuildVariant>> {
+        let available = self.variants.clone()
+            .or_else(|| config.variants.clone())
+            .unwrap_or_else(BuildVariant::defaults);
+        let mut variants = match self.variant_names.is_empty() {
+            true => available,
+            false => self.variant_names.iter()
+                .map(|name| available.iter()
+                    .chain(BuildVariant::defaults().iter())
+                    .find(|f| f.name == *name)
+                    .cloned()
+                    .ok_or_else(|| Error::Configuration {
+                        message: format!("No build variant named '{}'. The configured variants are: {}.", name,
+                                         available.iter().map(|f| f.name.as_str()).collect::<Vec<&str>>().join(", ")),
+                    }))
+                .collect::<Result<Vec<BuildVariant>>>()?,
+        };
+        for variant in &mut variants {
+            for feature in &self.features {
+                if !variant.cargo.features.contains(feature) {
+                    variant.cargo.features.push(feature.clone());
+                }
+            }
+            variant.cargo.no_default_features |= self.no_default_features;
+        }
+        Ok(variants)
+    }
+
+    /// Tells cargo to rerun the build script only when the package's inputs or the relevant variables change,
+    /// instead of after any change to the crate. Nothing is printed outside of build scripts.
+    fn emit_rerun_directives(cargo_package: Option<&CargoPackage>) {
+        if env::var_os("OUT_DIR").is_none() {
             return;
         }
+        for path in cargo_package.map(|f| f.rerun_if_changed()).unwrap_or_default() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+        for key in RERUN_IF_ENV_CHANGED {
+            println!("cargo:rerun-if-env-changed={}", key);
+        }
+    }
 
-        let cargo_package = CargoPackage::new(
+    /// Runs unless `SKIP_UNIFFI_SWIFTPKG_GEN` is set, i.e. inside the per-target cargo builds.
+    fn run_unless_skipped(self, write_packages: bool) -> Result<Report> {
+        if env::var(SKIP_UNIFFI_SWIFTPKG_GEN).unwrap_or_else(|_| "false".to_string()).to_lowercase() == "true" {
+            Builder::emit_rerun_directives(None);
+            return Ok(Report { skipped: true, ..Report::default() });
+        }
+        let dry_run = self.dry_run;
+        self.run(dry_run, write_packages)
+    }
+
+    fn cargo_package(&self) -> Result<CargoPackage> {
+        if !self.create_path.is_file() {
+            return Err(Error::Metadata {
+                manifest_path: self.create_path.clone(),
+                message: "Cargo.toml not found. Run from a build script or set CARGO_MANIFEST_DIR.".to_string(),
+            });
+        }
+
+        CargoPackage::new(
             self.create_path.as_path(),
-            Some(&self.crate_package_name),
+            self.crate_package_name.as_ref(),
             self.crate_lib_name.as_ref(),
             self.xcode_framework_name.as_ref(),
-            self.xcode_framework_path.as_path());
+            self.xcode_framework_path.as_deref(),
+            &self.udl_sources)
+    }
 
-        // for udl_item in &cargo_package.udl_absolute_files_path {
-        //     uniffi_build::generate_scaffolding(udl_item.0.to_str().unwrap())
-        //         .unwrap();
-        // }
+    fn run(self, dry_run: bool, write_packages: bool) -> Result<Report> {
+        let cargo_package = self.cargo_package()?;
+        Builder::emit_rerun_directives(Some(&cargo_package));
 
-        //println!("***************** cargo_package: {:?}", cargo_package);
+        let command = self.command_builder();
+        let context = if dry_run {
+            BuildContext::dry_run(command)
+        } else {
+            BuildContext::new(command)
+        };
+        let build_dir = self.build_dir.clone().unwrap_or_else(|| cargo_package.default_build_dir());
+        context.update_plan(|plan| {
+            plan.package = cargo_package.planned();
+            plan.build_dir = build_dir.clone();
+        });
 
-        let xc = XCFrameworkBuilder::new(cargo_package);
-        let frameworks = xc.build();
+        // Explicit Builder calls override the [package.metadata.swiftpkg] table.
+        let config = cargo_package.config.clone();
+        let slices = self.slices(&config);
+        let variants = self.variants(&config)?;
+        let mut minimum_versions = config.minimum_versions;
+        minimum_versions.merge(&self.minimum_versions);
 
+        let fingerprints = Arc::new(Fingerprints::load(&build_dir, self.force));
+        let mut xc = XCFrameworkBuilder::new(cargo_package, context.clone())
+            .with_fingerprints(fingerprints.clone())
+            .with_build_dir(build_dir)
+            .with_offline(self.offline);
+        if let Some(slices) = slices {
+            xc = xc.with_slices(slices);
+        }
+        if let Some(toolchain) = &self.toolchain {
+            xc = xc.with_toolchain(toolchain.clone());
+        }
+        xc = xc.with_variants(variants)
+            .with_environment(self.environment.clone())
+            .with_minimum_versions(minimum_versions)
+            .with_jobs(self.jobs);
+        let frameworks = xc.build()?;
+
+        let mut report = Report {
+            unchanged: fingerprints.unchanged(),
+            ..Report::default()
+        };
         for item in frameworks {
+            report.xc_frameworks.push(item.xc_frameworks_uri.clone());
+            if !write_packages {
+                continue;
+            }
             let swift_package: SwiftPackageItem = SwiftPackageItem::new(item);
-            //println!("swift_package: {:?}", swift_package);
-            swift_package.build();
+            let swift_package = swift_package.build(&context)?;
+            report.swift_packages.push(swift_package.swift_package_dir());
+        }
+        if context.is_dry_run() {
+            let mut plan = context.recorded_plan();
+            plan.xc_frameworks = report.xc_frameworks.clone();
+            plan.swift_packages = report.swift_packages.clone();
+            plan.unchanged = report.unchanged.clone();
+            report.plan = Some(plan);
         }
+        Ok(report)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use super::*;
+    use super::test_fixtures::*;
+
+    // Scripts the output of the uniffi-bindgen binary, which the `bindgen` feature replaces.
+    #[cfg(not(feature = "bindgen"))]
+    #[test]
+    fn generates_packages_with_recording_runner() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let runner = Arc::new(RecordingCommandRunner::new()
+            .respond_with("uniffi-bindgen", fake_uniffi_bindgen)
+            .respond_with("xcodebuild", fake_xcodebuild));
+
+        let report = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner.clone())
+            .try_generate()
+            .unwrap();
+
+        let package_dir = dir.path().join("xcode").join("FixtureKit");
+        assert_eq!(report.swift_packages, vec![
+            dir.path().join("xcode").join("FixtureKit_debug"),
+            package_dir.clone(),
+        ]);
+        let manifest = fs::read_to_string(package_dir.join("Package.swift")).unwrap();
+        assert!(manifest.contains("name: \"FixtureKit\""));
+        assert!(manifest.contains("platforms: [\n        .iOS(\"13.0\"),\n        .macOS(\"10.13\")\n"));
+        assert!(manifest.contains(".binaryTarget(name: \"lib_fixturekit_xc\", path: \"./FixtureKit.xcframework\")"));
+        assert!(package_dir.join("Tests").join("FixtureKitTests").join("FixtureKitTests.swift").exists());
+        assert!(package_dir.join("FixtureKit.xcframework").join("Info.plist").is_file());
+        assert!(package_dir.join("Sources").join("FixtureKit").join("FixtureKit.swift").is_file());
+        let headers_dir = package_dir.join("Sources").join("FixtureKit").join("Headers");
+        assert!(headers_dir.join("FixtureKitFFI.h").is_file());
+        assert!(headers_dir.join("module.modulemap").is_file());
+
+        let calls = runner.calls().iter().map(|f| f.to_string()).collect::<Vec<String>>();
+        assert!(calls.iter().any(|f| f.contains("uniffi-bindgen generate")));
+        assert!(!calls.iter().any(|f| ["cp ", "mv ", "rm ", "mkdir "].iter().any(|c| f.starts_with(c))));
+        assert!(calls.iter().any(|f| f.contains("--target aarch64-apple-ios ") && f.contains("--release")));
+        let package = CargoPackage::new(&dir.path().join("Cargo.toml"), None, None, None, None, &UdlSources::default())
+            .unwrap();
+        let target_dir = package.default_build_dir().join("target").join("aarch64-apple-ios");
+        assert!(calls.iter().any(|f| f.contains(&format!("--target-dir {} ", target_dir.display()))));
+        assert_eq!(calls.iter().filter(|f| f.contains("xcodebuild -create-xcframework")).count(), 2);
+    }
+
     #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    fn builds_without_packages_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let builder = || Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_build_dir(dir.path().join("build"))
+            .with_command_runner(RecordingCommandRunner::new()
+                .respond_with("uniffi-bindgen", fake_uniffi_bindgen)
+                .respond_with("xcodebuild", fake_xcodebuild));
+
+        let report = builder().try_build().unwrap();
+        assert_eq!(report.xc_frameworks.len(), 2);
+        assert!(report.swift_packages.is_empty());
+        assert!(!dir.path().join("xcode").exists());
+
+        assert!(report.xc_frameworks.iter().all(|f| f.starts_with(dir.path().join("build").join("universal"))));
+        assert!(!dir.path().join("target").exists());
+
+        assert_eq!(builder().clean().unwrap(), vec![dir.path().join("build")]);
+        assert!(!dir.path().join("build").exists());
+        assert!(builder().clean().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "bindgen")]
+    #[test]
+    fn generates_packages_with_the_linked_bindgen() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let accounts_udl = dir.path().join("src").join("accounts.udl");
+        fs::write(&accounts_udl, "namespace accounts {};\n").unwrap();
+        let generate = || {
+            let runner = Arc::new(RecordingCommandRunner::new()
+                .respond_with("build --locked", fake_cargo_build)
+                .respond_with("lipo -create", fake_lipo)
+                .respond_with("xcodebuild", fake_xcodebuild));
+            let report = Builder::new()
+                .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+                .with_command_runner(runner.clone())
+                .with_platforms([Platform::Ios])
+                .with_variants([BuildVariant::release()])
+                .try_generate()
+                .unwrap();
+            assert!(!runner.calls().iter().any(|f| f.program.contains("uniffi")));
+            report
+        };
+
+        let report = generate();
+        assert!(report.unchanged.is_empty());
+        let sources = report.swift_packages[0].join("Sources").join("FixtureKit");
+        let headers_dir = sources.join("Headers");
+        let module_map = fs::read_to_string(headers_dir.join("module.modulemap")).unwrap();
+        for namespace in ["accounts", "fixture_kit"] {
+            assert!(sources.join(format!("{}.swift", namespace)).is_file(), "missing {}.swift", namespace);
+            assert!(headers_dir.join(format!("{}FFI.h", namespace)).is_file(), "missing {}FFI.h", namespace);
+            assert!(module_map.contains(&format!("module {}FFI {{", namespace)));
+            assert!(module_map.contains(&format!("header \"{}FFI.h\"", namespace)));
+        }
+
+        assert_eq!(generate().unchanged.len(), 4);
+        fs::write(&accounts_udl, "namespace accounts { u32 answer(); };\n").unwrap();
+        let report = generate();
+        assert!(!report.unchanged.contains(&"uniffi-bindgen".to_string()));
+        assert!(fs::read_to_string(sources.join("accounts.swift")).unwrap().contains("func answer()"));
     }
 }
@@ -0,0 +1,141 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use super::command_runner::ProcessCommand;
+
+/// Everything a generator run would do, as resolved by `Builder::plan` without touching the disk.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildPlan {
+    pub package: PlannedPackage,
+    /// Directory the per-target cargo builds run in.
+    pub build_dir: PathBuf,
+    /// Number of targets compiled at once. The plan lists their commands as if they were built one at a time.
+    pub jobs: usize,
+    /// Every Rust target triple that is compiled, per build variant and xcframework slice.
+    pub targets: Vec<PlannedTarget>,
+    /// Stages that would be skipped because their inputs are unchanged since the last run, e.g. `lipo debug ios`.
+    pub unchanged: Vec<String>,
+    /// Every process that would be spawned, in execution order.
+    pub commands: Vec<ProcessCommand>,
+    /// Every filesystem change, in execution order.
+    pub file_operations: Vec<FileOperation>,
+    pub xc_frameworks: Vec<PathBuf>,
+    pub swift_packages: Vec<PathBuf>,
+}
+
+/// The crate and Swift package resolved from the cargo metadata.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedPackage {
+    pub crate_name: String,
+    pub lib_name: String,
+    pub manifest_path: PathBuf,
+    pub swift_package_name: String,
+    pub swift_package_path: PathBuf,
+    pub udl_files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedTarget {
+    /// Name of the `BuildVariant`.
+    pub variant: String,
+    pub slice: String,
+    pub triple: String,
+    /// The deployment target and the variables added through `Builder::with_target_env`.
+    pub envs: Vec<(String, String)>,
+    /// Flags added through `Builder::with_rustflags`.
+    pub rustflags: Vec<String>,
+}
+
+/// A filesystem change. `from` paths may be glob patterns for files that only exist once earlier steps ran.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum FileOperation {
+    CreateDir { path: PathBuf },
+    Write { path: PathBuf, overwrite: bool },
+    Remove { path: PathBuf },
+    Copy { from: PathBuf, to: PathBuf },
+    Move { from: PathBuf, to: PathBuf },
+    /// Concatenates the `from` files into `to` and removes them.
+    Merge { from: PathBuf, to: PathBuf },
+}
+
+impl BuildPlan {
+    /// Every path the run would create or overwrite.
+    pub fn written_paths(&self) -> Vec<&PathBuf> {
+        self.file_operations.iter()
+            .filter_map(|f| match f {
+                FileOperation::CreateDir { path } | FileOperation::Write { path, .. } => Some(path),
+                FileOperation::Copy { to, .. } | FileOperation::Move { to, .. } | FileOperation::Merge { to, .. } => Some(to),
+                FileOperation::Remove { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Every path the run would delete.
+    pub fn deleted_paths(&self) -> Vec<&PathBuf> {
+        self.file_operations.iter()
+            .filter_map(|f| match f {
+                FileOperation::Remove { path } => Some(path),
+                FileOperation::Move { from, .. } | FileOperation::Merge { from, .. } => Some(from),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::{Builder, CommandOutput, RecordingCommandRunner};
+    use crate::cargo_package::CargoPackage;
+    use crate::udl::UdlSources;
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn plan_records_the_run_without_executing_it() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let runner = Arc::new(RecordingCommandRunner::new()
+            .respond("rustup target list", CommandOutput::success("aarch64-apple-ios\n")));
+
+        let plan = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner.clone())
+            .plan()
+            .unwrap();
+
+        assert!(!dir.path().join("xcode").exists());
+        let mut calls = runner.calls().iter().map(|f| f.to_string()).collect::<Vec<String>>();
+        // The linked-in bindgen of the `bindgen` feature has no binary to ask for its version.
+        if !cfg!(feature = "bindgen") {
+            assert!(calls.remove(0).ends_with("uniffi-bindgen --version"));
+        }
+        assert_eq!(calls.len(), 2);
+        assert!(calls[0].starts_with("rustup target list --installed"));
+        assert!(calls[1].ends_with("rustc -vV"));
+
+        assert_eq!(plan.package.crate_name, "fixture_kit");
+        assert_eq!(plan.package.swift_package_name, "FixtureKit");
+        assert_eq!(plan.package.udl_files, vec![dir.path().join("src").join("fixture_kit.udl")]);
+        assert!(plan.targets.iter().any(|f| f.variant == "release" && f.triple == "aarch64-apple-ios"));
+        let commands = plan.commands.iter().map(|f| f.to_string()).collect::<Vec<String>>();
+        for program in ["rustup target add", "cargo build", "generate", "--language swift", "lipo -create", "xcodebuild"] {
+            assert!(commands.iter().any(|f| f.contains(program)), "missing {}", program);
+        }
+        let package_swift = dir.path().join("xcode").join("FixtureKit").join("Package.swift");
+        assert!(plan.written_paths().contains(&&package_swift));
+        assert_eq!(plan.swift_packages.len(), 2);
+        // The target dir is `CARGO_TARGET_DIR` when it is set, as in CI setups sharing one.
+        let package = CargoPackage::new(&dir.path().join("Cargo.toml"), None, None, None, None, &UdlSources::default())
+            .unwrap();
+        assert!(plan.build_dir.starts_with(package.target_dir.join("swiftpkg")));
+        assert!(plan.build_dir.file_name().unwrap().to_string_lossy().starts_with("fixture_kit-"));
+
+        let parsed: BuildPlan = serde_json::from_str(&plan.to_json()).unwrap();
+        assert_eq!(parsed, plan);
+    }
+}
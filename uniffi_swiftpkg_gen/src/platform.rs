@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt::Formatter;
+use serde::{Deserialize, Serialize};
+use super::error::*;
+
+/// Targets without prebuilt std in rustup. They are built with `-Zbuild-std` on the nightly toolchain.
+const BUILD_STD_TRIPLES: [&str; 4] = [
+    "x86_64-apple-tvos",
+    "arm64_32-apple-watchos",
+    "armv7k-apple-watchos",
+    "x86_64-apple-watchos-sim",
+];
+
+/// An Apple platform variant the xcframework carries one library for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Platform {
+    Ios,
+    IosSimulator,
+    MacOs,
+    /// iOS apps running on macOS.
+    MacCatalyst,
+    TvOs,
+    TvOsSimulator,
+    WatchOs,
+    WatchOsSimulator,
+    VisionOs,
+    VisionOsSimulator,
+}
+
+impl Platform {
+    /// Platforms built when none are selected through `Builder::with_platforms`.
+    pub const DEFAULT: [Platform; 3] = [Platform::Ios, Platform::IosSimulator, Platform::MacOs];
+
+    pub const ALL: [Platform; 10] = [
+        Platform::Ios, Platform::IosSimulator, Platform::MacOs, Platform::MacCatalyst, Platform::TvOs,
+        Platform::TvOsSimulator, Platform::WatchOs, Platform::WatchOsSimulator, Platform::VisionOs,
+        Platform::VisionOsSimulator,
+    ];
+
+    /// Short name, also used for the directory the platform's fat library is written to.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Ios => "ios",
+            Platform::IosSimulator => "ios_sim",
+            Platform::MacOs => "osx",
+            Platform::MacCatalyst => "maccatalyst",
+            Platform::TvOs => "tvos",
+            Platform::TvOsSimulator => "tvos_sim",
+            Platform::WatchOs => "watchos",
+            Platform::WatchOsSimulator => "watchos_sim",
+            Platform::VisionOs => "visionos",
+            Platform::VisionOsSimulator => "visionos_sim",
+        }
+    }
+
+    /// Rust targets combined into the platform's library unless a `Slice` says otherwise.
+    pub fn default_triples(&self) -> &'static [&'static str] {
+        match self {
+            Platform::Ios => &["aarch64-apple-ios"],
+            Platform::IosSimulator => &["aarch64-apple-ios-sim", "x86_64-apple-ios"],
+            Platform::MacOs => &["aarch64-apple-darwin", "x86_64-apple-darwin"],
+            Platform::MacCatalyst => &["aarch64-apple-ios-macabi", "x86_64-apple-ios-macabi"],
+            Platform::TvOs => &["aarch64-apple-tvos"],
+            Platform::TvOsSimulator => &["aarch64-apple-tvos-sim", "x86_64-apple-tvos"],
+            Platform::WatchOs => &["aarch64-apple-watchos", "arm64_32-apple-watchos"],
+            Platform::WatchOsSimulator => &["aarch64-apple-watchos-sim", "x86_64-apple-watchos-sim"],
+            Platform::VisionOs => &["aarch64-apple-visionos"],
+            Platform::VisionOsSimulator => &["aarch64-apple-visionos-sim"],
+        }
+    }
+
+    /// Name of the platform in Package.swift, shared by devices and simulators.
+    pub(crate) fn swift_platform(&self) -> &'static str {
+        match self {
+            Platform::Ios | Platform::IosSimulator => "iOS",
+            Platform::MacOs => "macOS",
+            Platform::MacCatalyst => "macCatalyst",
+            Platform::TvOs | Platform::TvOsSimulator => "tvOS",
+            Platform::WatchOs | Platform::WatchOsSimulator => "watchOS",
+            Platform::VisionOs | Platform::VisionOsSimulator => "visionOS",
+        }
+    }
+
+    /// Minimum OS version used unless one is set through `Builder::with_minimum_version`.
+    pub fn default_minimum_version(&self) -> &'static str {
+        match self {
+            Platform::Ios | Platform::IosSimulator => "13.0",
+            Platform::MacOs => "10.13",
+            Platform::MacCatalyst => "14.0",
+            Platform::TvOs | Platform::TvOsSimulator => "13.0",
+            Platform::WatchOs | Platform::WatchOsSimulator => "6.0",
+            Platform::VisionOs | Platform::VisionOsSimulator => "1.0",
+        }
+    }
+
+    /// Variable rustc and the linker read the minimum OS version from.
+    pub(crate) fn deployment_target_env(&self) -> &'static str {
+        match self {
+            Platform::Ios | Platform::IosSimulator | Platform::MacCatalyst => "IPHONEOS_DEPLOYMENT_TARGET",
+            Platform::MacOs => "MACOSX_DEPLOYMENT_TARGET",
+            Platform::TvOs | Platform::TvOsSimulator => "TVOS_DEPLOYMENT_TARGET",
+            Platform::WatchOs | Platform::WatchOsSimulator => "WATCHOS_DEPLOYMENT_TARGET",
+            Platform::VisionOs | Platform::VisionOsSimulator => "XROS_DEPLOYMENT_TARGET",
+        }
+    }
+
+    /// Whether the target has no prebuilt standard library and must be built with `-Zbuild-std`.
+    pub(crate) fn requires_build_std(triple: &str) -> bool {
+        BUILD_STD_TRIPLES.contains(&triple)
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parses the short name, e.g. `ios_sim`, or the serialized name, e.g. `ios_simulator`.
+impl std::str::FromStr for Platform {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Platform::ALL.into_iter()
+            .find(|f| f.name() == s || serde_json::to_value(f).map(|v| v == s).unwrap_or(false))
+            .ok_or_else(|| Error::Configuration {
+                message: format!("Unknown platform '{}'. Expected one of: {}.", s,
+                                 Platform::ALL.map(|f| f.name()).join(", ")),
+            })
+    }
+}
+
+/// Minimum OS version per Package.swift platform, shared by devices and simulators.
+///
+/// The same version is the deployment target of the cargo builds and the `platforms:` entry of Package.swift.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct MinimumVersions {
+    versions: HashMap<&'static str, String>,
+}
+
+impl MinimumVersions {
+    pub(crate) fn set(&mut self, platform: Platform, version: String) {
+        self.versions.insert(platform.swift_platform(), version);
+    }
+
+    /// Takes the versions set in `other`, keeping the others.
+    pub(crate) fn merge(&mut self, other: &MinimumVersions) {
+        self.versions.extend(other.versions.iter().map(|(k, v)| (*k, v.clone())));
+    }
+
+    pub(crate) fn version(&self, platform: Platform) -> &str {
+        self.versions.get(platform.swift_platform())
+            .map(|f| f.as_str())
+            .unwrap_or_else(|| platform.default_minimum_version())
+    }
+
+    /// The `platforms:` entry of Package.swift, e.g. `.iOS("13.0")`.
+    pub(crate) fn swift_supported_platform(&self, platform: Platform) -> String {
+        format!(".{}(\"{}\")", platform.swift_platform(), self.version(platform))
+    }
+}
+
+/// One library of the xcframework: a platform and the Rust targets lipo'd together for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slice {
+    pub platform: Platform,
+    pub triples: Vec<String>,
+}
+
+impl Slice {
+    pub fn new<I, S>(platform: Platform, triples: I) -> Self
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String> {
+        Slice {
+            platform,
+            triples: triples.into_iter().map(|f| f.into()).collect(),
+        }
+    }
+
+    /// Checks that the slices describe a buildable xcframework.
+    pub(crate) fn validate(slices: &[Slice]) -> Result<()> {
+        if slices.is_empty() {
+            return Err(Error::Configuration {
+                message: "No platform selected. Select at least one platform to build.".to_string(),
+            });
+        }
+        for (index, slice) in slices.iter().enumerate() {
+            if slice.triples.is_empty() {
+                return Err(Error::Configuration {
+                    message: format!("The {} slice has no Rust target.", slice.platform),
+                });
+            }
+            if slices[..index].iter().any(|f| f.platform == slice.platform) {
+                return Err(Error::Configuration {
+                    message: format!("The {} platform is selected more than once.", slice.platform),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<Platform> for Slice {
+    fn from(platform: Platform) -> Self {
+        Slice::new(platform, platform.default_triples().iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+    use super::*;
+    use crate::{Builder, BuildVariant, RecordingCommandRunner};
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn parses_platform_names() {
+        assert_eq!("ios_sim".parse::<Platform>().unwrap(), Platform::IosSimulator);
+        assert_eq!("ios_simulator".parse::<Platform>().unwrap(), Platform::IosSimulator);
+        assert_eq!("osx".parse::<Platform>().unwrap(), Platform::MacOs);
+        assert!("android".parse::<Platform>().is_err());
+    }
+
+    #[test]
+    fn validates_selected_slices() {
+        let slices = Platform::DEFAULT.into_iter().map(Slice::from).collect::<Vec<Slice>>();
+        assert!(Slice::validate(&slices).is_ok());
+        assert_eq!(slices[1].triples, vec!["aarch64-apple-ios-sim", "x86_64-apple-ios"]);
+
+        assert!(Slice::validate(&[]).is_err());
+        assert!(Slice::validate(&[Slice::new(Platform::Ios, Vec::<String>::new())]).is_err());
+        assert!(Slice::validate(&[Slice::from(Platform::Ios), Slice::new(Platform::Ios, ["x86_64-apple-ios"])]).is_err());
+    }
+
+    #[test]
+    fn shares_minimum_versions_between_devices_and_simulators() {
+        let mut versions = MinimumVersions::default();
+        versions.set(Platform::IosSimulator, "15.0".to_string());
+        assert_eq!(versions.version(Platform::Ios), "15.0");
+        assert_eq!(versions.version(Platform::MacOs), "10.13");
+        assert_eq!(versions.swift_supported_platform(Platform::Ios), ".iOS(\"15.0\")");
+        assert_eq!(Platform::MacCatalyst.deployment_target_env(), "IPHONEOS_DEPLOYMENT_TARGET");
+    }
+
+    #[test]
+    fn tier_3_targets_require_build_std() {
+        assert!(Platform::requires_build_std("x86_64-apple-tvos"));
+        assert!(Platform::requires_build_std("arm64_32-apple-watchos"));
+        assert!(!Platform::requires_build_std("aarch64-apple-tvos"));
+        assert!(!Platform::requires_build_std("aarch64-apple-ios-macabi"));
+    }
+
+    #[test]
+    fn builds_only_the_selected_slices() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+
+        let plan = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new())
+            .with_slices([Slice::from(Platform::Ios), Slice::new(Platform::IosSimulator, ["aarch64-apple-ios-sim"])])
+            .plan()
+            .unwrap();
+
+        let triples = plan.targets.iter()
+            .filter(|f| f.variant == "release")
+            .map(|f| (f.slice.as_str(), f.triple.as_str()))
+            .collect::<Vec<(&str, &str)>>();
+        assert_eq!(triples, vec![("ios", "aarch64-apple-ios"), ("ios_sim", "aarch64-apple-ios-sim")]);
+        let xcodebuild = plan.commands.iter().find(|f| f.program == "xcodebuild").unwrap();
+        assert_eq!(xcodebuild.args.iter().filter(|f| f.as_str() == "-library").count(), 2);
+        assert!(!plan.commands.iter().any(|f| f.args.iter().any(|arg| arg.contains("darwin"))));
+
+        let error = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new())
+            .with_platforms([])
+            .plan()
+            .unwrap_err();
+        assert!(matches!(error, Error::Configuration { .. }));
+    }
+
+    #[test]
+    fn minimum_version_sets_the_deployment_target_of_devices_and_simulators() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+
+        let plan = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new())
+            .with_platforms([Platform::Ios, Platform::IosSimulator])
+            .with_variants([BuildVariant::release()])
+            .with_minimum_version(Platform::Ios, "15.0")
+            .plan()
+            .unwrap();
+
+        let builds = plan.commands.iter()
+            .filter(|f| f.args.contains(&"build".to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(builds.len(), 3);
+        assert!(builds.iter()
+            .all(|f| f.envs.contains(&("IPHONEOS_DEPLOYMENT_TARGET".to_string(), "15.0".to_string()))));
+    }
+
+    #[test]
+    fn builds_tier_3_targets_and_declares_their_platforms() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let runner = Arc::new(RecordingCommandRunner::new()
+            .respond_with("uniffi-bindgen", fake_uniffi_bindgen)
+            .respond_with("xcodebuild", fake_xcodebuild));
+
+        Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner.clone())
+            .with_platforms([Platform::Ios, Platform::TvOs, Platform::TvOsSimulator, Platform::MacCatalyst, Platform::VisionOs])
+            .try_generate()
+            .unwrap();
+
+        let calls = runner.calls().iter().map(|f| f.to_string()).collect::<Vec<String>>();
+        assert!(calls.iter().any(|f| f.starts_with("rustup component add rust-src --toolchain nightly")));
+        assert!(!calls.iter().any(|f| f == "rustup target add x86_64-apple-tvos"));
+        assert!(calls.iter().any(|f| f.contains("cargo +nightly build")
+            && f.contains("-Zbuild-std=std,panic_abort") && f.contains("--target x86_64-apple-tvos ")));
+        assert!(calls.iter().any(|f| f.contains("cargo build") && f.contains("--target aarch64-apple-tvos-sim ")));
+
+        let manifest = fs::read_to_string(dir.path().join("xcode").join("FixtureKit").join("Package.swift")).unwrap();
+        assert!(manifest.starts_with("// swift-tools-version:5.9\n"));
+        assert!(manifest.contains("platforms: [\n        .iOS(\"13.0\"),\n        .tvOS(\"13.0\"),\n        .macCatalyst(\"14.0\"),\n        .visionOS(\"1.0\")\n"));
+        assert!(manifest.contains("condition: .when(platforms: [.iOS, .tvOS, .macCatalyst, .visionOS])"));
+    }
+}
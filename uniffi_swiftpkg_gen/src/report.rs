@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+use super::plan::BuildPlan;
+
+/// Summary of a successful `Builder::try_generate` run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// `true` when generation was skipped because `SKIP_UNIFFI_SWIFTPKG_GEN` is set.
+    pub skipped: bool,
+    /// Every xcframework that was created.
+    pub xc_frameworks: Vec<PathBuf>,
+    /// Every Swift package directory that was written.
+    pub swift_packages: Vec<PathBuf>,
+    /// Stages skipped because their inputs are unchanged since the last run,
+    /// e.g. `cargo debug aarch64-apple-ios` or `xcframework release`.
+    pub unchanged: Vec<String>,
+    /// What would have been done, when running with `Builder::with_dry_run`.
+    /// Nothing was built or written in that case.
+    pub plan: Option<BuildPlan>,
+}
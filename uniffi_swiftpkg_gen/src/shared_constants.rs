@@ -1,2 +1,13 @@
 
 pub(crate) const SKIP_UNIFFI_SWIFTPKG_GEN: &str = "SKIP_UNIFFI_SWIFTPKG_GEN";
+
+/// Variables besides the crate's files that the generated packages depend on: they decide whether
+/// generation runs, the toolchain the targets are built with, and where they are built.
+pub(crate) const RERUN_IF_ENV_CHANGED: [&str; 6] = [
+    SKIP_UNIFFI_SWIFTPKG_GEN,
+    "RUSTUP_TOOLCHAIN",
+    "RUSTUP_HOME",
+    "CARGO_HOME",
+    "CARGO_TARGET_DIR",
+    "CARGO_BUILD_TARGET_DIR",
+];
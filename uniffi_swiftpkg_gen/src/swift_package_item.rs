@@ -1,99 +1,78 @@
 use std::path::{Path, PathBuf};
-use colored::Colorize;
-use std::process::{exit};
 use crate::ext::*;
 use pathdiff::diff_paths;
 use super::xc_framework_generator::*;
-use super::command_builder::*;
+use super::error::*;
+use super::build_context::BuildContext;
+use super::platform::Platform;
 
 #[derive(Debug)]
 pub(crate) struct SwiftPackageItem {
     framework_item: XCFrameworkItem,
-    command: CommandBuilder,
 }
 
 impl SwiftPackageItem {
     /// path: Path to the directory to create Swift Package
     pub(crate) fn new(framework_item: XCFrameworkItem) -> Self {
-        let command =  CommandBuilder::new();
         SwiftPackageItem {
             framework_item,
-            command
         }
     }
 
     #[allow(unused)]
-    pub(crate) fn build(mut self) -> Self {
-        self.swift_package_dir()
-            .create_dir_if_not_exist("Unable to create Swift Package directory.");
-        self.sources_base_dir()
-            .create_dir_if_not_exist("Unable to create Swift Package Sources directory.");
-        self.tests_base_dir()
-            .create_dir_if_not_exist("Unable to create Swift Package Tests directory.");
+    pub(crate) fn build(self, context: &BuildContext) -> Result<Self> {
+        context.create_dir(&self.swift_package_dir())?;
+        context.create_dir(&self.sources_base_dir())?;
+        context.create_dir(&self.tests_base_dir())?;
 
-        self.sources_package_dir()
-            .create_dir_if_not_exist("Unable to create Swift Package Sources lib directory.");
-        self.tests_package_dir()
-            .create_dir_if_not_exist("Unable to create Swift Package Tests lib directory.");
+        context.create_dir(&self.sources_package_dir())?;
+        context.create_dir(&self.tests_package_dir())?;
 
-        self.headers_dir()
-            .create_dir_if_not_exist("Unable to create Swift Package Headers directory.");
+        // The headers and Swift files of the bindings are replaced as a whole, dropping those of removed namespaces.
+        context.remove(&self.headers_dir())?;
+        context.remove_matches(&self.sources_package_dir(), "*.swift")?;
+        context.create_dir(&self.headers_dir())?;
 
-        self.resources_dir()
-            .create_dir_if_not_exist("Unable to create Swift Package Resources directory.");
+        context.create_dir(&self.resources_dir())?;
 
-        self.package_swift_uri()
-            .write_file(self.build_package_swift_file_contents(), true)
-            .expect("Generating Swift Package file failed");
-        self.tests_swift_file_uri()
-            .write_file(self.build_tests_swift_file_contents(), false)
-            .expect("Generating Swift Package Tests file failed");
+        context.write_file(&self.package_swift_uri(), self.build_package_swift_file_contents()?, true)?;
+        context.write_file(&self.tests_swift_file_uri(), self.build_tests_swift_file_contents(), false)?;
 
-        self.package_readme_md().write_file(self.build_readme_md_content(), false);
+        context.write_file(&self.package_readme_md(), self.build_readme_md_content(), false)?;
 
-        self.git_ignore_file().write_file(self.build_git_ignore_file(), false);
+        context.write_file(&self.git_ignore_file(), self.build_git_ignore_file(), false)?;
 
         // copy Required files
-        let status = self.command.args_stream([
-            format!("cp -R {}/* {}/",
-                    self.framework_item.headers_path.to_str().unwrap(),
-                    self.headers_dir().to_str().unwrap())]);
-        if !status.success() {
-            eprintln!("{}{}", "Copying headers files failed. ".red(), status);
-            exit(1);
-        }
+        context.copy_dir_contents(&self.framework_item.headers_path, &self.headers_dir())?;
+        context.copy_dir_contents(&self.framework_item.swift_files_path, &self.sources_package_dir())?;
 
-        let status = self.command.args_stream([
-            format!("cp -R {}/* {}/",
-                    self.framework_item.swift_files_path.to_str().unwrap(),
-                    self.sources_package_dir().to_str().unwrap())]);
-        if !status.success() {
-            eprintln!("{}{}", "Copying swift files failed. ".red(), status);
-            exit(1);
-        }
-
-        // Copy XC Framework
-        let status = self.command.args_stream([
-            format!("rm -rf {1}/{2}; cp -R {0} {1}/",
-                    self.framework_item.xc_frameworks_uri.to_str().unwrap(),
-                    self.swift_package_dir().to_str().unwrap(),
-                    self.framework_item.xc_frameworks_uri.file_name().unwrap().to_str().unwrap())]);
-        if !status.success() {
-            eprintln!("{}{}", "Copying xc framework files failed. ".red(), status);
-            exit(1);
-        }
+        // Copy XC Framework next to its final location first, so the package never holds a partial copy.
+        let xc_framework_dir = self.swift_package_dir().join(self.xc_framework_file_name()?);
+        let staging_dir = self.swift_package_dir().join(format!(".{}.tmp", self.xc_framework_file_name()?));
+        context.copy_recursively(&self.framework_item.xc_frameworks_uri, &staging_dir)?;
+        context.remove(&xc_framework_dir)?;
+        context.move_path(&staging_dir, &xc_framework_dir)?;
 
-        self
+        Ok(self)
     }
 
-    fn swift_package_dir(&self) -> PathBuf {
+    /// Directory of the generated Swift package.
+    pub(crate) fn swift_package_dir(&self) -> PathBuf {
         Path::new(
-            format!("{}{}", &self.framework_item.swift_package_build_path.to_str().unwrap(),
-                    if self.framework_item.build_type == BuildType::Debug { "_debug" } else { "" })
+            format!("{}{}", &self.framework_item.swift_package_build_path.display(),
+                    self.framework_item.variant.suffix)
                 .as_str()
         ).to_path_buf()
     }
 
+    fn xc_framework_file_name(&self) -> Result<String> {
+        self.framework_item.xc_frameworks_uri.file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .ok_or_else(|| Error::io(&self.framework_item.xc_frameworks_uri,
+                                     std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                                         "xcframework path has no file name")))
+    }
+
     fn git_ignore_file(&self) -> PathBuf {
         self.swift_package_dir().join(".gitignore")
     }
@@ -150,9 +129,23 @@ impl SwiftPackageItem {
     }
 
     #[allow(unused)]
-    fn build_package_swift_file_contents(&self) -> String {
+    fn build_package_swift_file_contents(&self) -> Result<String> {
+        let headers_dir = diff_paths(self.headers_dir(), self.swift_package_dir())
+            .ok_or_else(|| Error::io(self.headers_dir(),
+                                     std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                                         "Unable to get headers directory differential path.")))?;
+        let xc_framework_file_name = self.xc_framework_file_name()?;
+
+        let swift_platforms = self.swift_platforms();
+        // visionOS is only known to PackageDescription from Swift 5.9 on.
+        let swift_tools_version = if swift_platforms.iter().any(|f| f.swift_platform() == "visionOS") {
+            "5.9"
+        } else {
+            "5.5"
+        };
+
         let mut content = String::new();
-        content.push_str("// swift-tools-version:5.5\n");
+        content.push_str(format!("// swift-tools-version:{}\n", swift_tools_version).as_str());
         content.push_str("// The swift-tools-version declares the minimum version of Swift required to build this package.\n");
         content.push_str(format!("// Swift Package: {}\n\n", self.framework_item.swift_package_name).as_str());
         content.push_str("import PackageDescription;\n\n");
@@ -161,10 +154,11 @@ impl SwiftPackageItem {
                                  self.framework_item.swift_package_name).as_str());
         content.push_str(format!("{}platforms: [\n",
                                  String::build_whitespaces(1)).as_str());
-        content.push_str(format!("{}.iOS(.v13),\n",
-                                 String::build_whitespaces(2)).as_str());
-        content.push_str(format!("{}.macOS(SupportedPlatform.MacOSVersion.v10_10)\n",
-                                 String::build_whitespaces(2)).as_str());
+        let supported_platforms = swift_platforms.iter()
+            .map(|f| format!("{}{}", String::build_whitespaces(2),
+                             self.framework_item.minimum_versions.swift_supported_platform(*f)))
+            .collect::<Vec<String>>();
+        content.push_str(format!("{}\n", supported_platforms.join(",\n")).as_str());
         content.push_str(format!("{}],\n",
                                  String::build_whitespaces(2)).as_str());
         content.push_str(format!("{}products: [\n",
@@ -192,18 +186,13 @@ impl SwiftPackageItem {
         content.push_str(format!("{}.systemLibrary(name: \"sys_lib_{}\", path: \"./{}\", pkgConfig: nil, providers: []),\n",
                                  String::build_whitespaces(2),
                                  self.framework_item.swift_package_name.to_lowercase(),
-                                 diff_paths(&self.headers_dir(), &self.swift_package_dir() )
-                                     .expect("Unable to get headers directory differential path.").to_str()
-                                     .expect("Unable to unwrap string"),
+                                 headers_dir.display(),
         ).as_str());
 
         content.push_str(format!("{}.binaryTarget(name: \"lib_{}_xc\", path: \"./{}\"),\n",
                                  String::build_whitespaces(2),
                                  self.framework_item.swift_package_name.to_lowercase(),
-                                 // diff_paths(&self.resources_dir(), &self.swift_package_dir())
-                                 //     .expect("Unable to get headers directory differential path.").to_str()
-                                 //     .expect("Unable to unwrap string"),
-            self.framework_item.xc_frameworks_uri.file_name().unwrap().to_str().unwrap()
+            xc_framework_file_name
         ).as_str());
 
         content.push_str(format!("{}.target(\n",
@@ -217,9 +206,13 @@ impl SwiftPackageItem {
                                  String::build_whitespaces(3)).as_str());
 
         // Define system library here
-        content.push_str(format!("{}.target(name: \"sys_lib_{}\", condition: .when(platforms: [.iOS, .macOS])),\n",
+        content.push_str(format!("{}.target(name: \"sys_lib_{}\", condition: .when(platforms: [{}])),\n",
                                  String::build_whitespaces(4),
-                                 self.framework_item.swift_package_name.to_lowercase()).as_str());
+                                 self.framework_item.swift_package_name.to_lowercase(),
+                                 swift_platforms.iter()
+                                     .map(|f| format!(".{}", f.swift_platform()))
+                                     .collect::<Vec<String>>()
+                                     .join(", ")).as_str());
 
         // Define binaryTarget dependency
         content.push_str(format!("{}.target(name: \"lib_{}_xc\")\n",
@@ -246,7 +239,18 @@ impl SwiftPackageItem {
         content.push_str(format!("{}]\n", String::build_whitespaces(1)).as_str());
         content.push_str(")\n");
 
-        content
+        Ok(content)
+    }
+
+    /// One platform per Package.swift platform name, as devices and simulators share it.
+    fn swift_platforms(&self) -> Vec<Platform> {
+        let mut platforms: Vec<Platform> = vec![];
+        for platform in &self.framework_item.platforms {
+            if !platforms.iter().any(|f| f.swift_platform() == platform.swift_platform()) {
+                platforms.push(*platform);
+            }
+        }
+        platforms
     }
 
     #[allow(unused)]
@@ -462,4 +466,68 @@ xcuserdata/
 "#);
         content
     }
-}
\ No newline at end of file
+}
+
+// Scripts the output of the uniffi-bindgen binary, which the `bindgen` feature replaces.
+#[cfg(all(test, not(feature = "bindgen")))]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::{Builder, CommandOutput, ProcessCommand, RecordingCommandRunner};
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn merges_the_module_maps_of_every_namespace() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        fs::write(dir.path().join("src").join("accounts.udl"), "namespace accounts {};\n").unwrap();
+        // Writes the files of the namespace named after the UDL file, as uniffi-bindgen does.
+        let bindgen = |command: &ProcessCommand| {
+            if command.args == ["--version"] {
+                return fake_uniffi_bindgen(command);
+            }
+            let namespace = Path::new(&command.args[1]).file_stem().unwrap().to_string_lossy().to_string();
+            let out_dir = PathBuf::from(command.args.last().unwrap());
+            fs::create_dir_all(&out_dir).unwrap();
+            fs::write(out_dir.join(format!("{}FFI.h", namespace)), "").unwrap();
+            fs::write(out_dir.join(format!("{}FFI.modulemap", namespace)),
+                      format!("module {0}FFI {{\n    header \"{0}FFI.h\"\n    export *\n}}\n", namespace)).unwrap();
+            fs::write(out_dir.join(format!("{}.swift", namespace)), "").unwrap();
+            CommandOutput::success("")
+        };
+        let builder = || Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_build_dir(dir.path().join("build"))
+            .with_command_runner(RecordingCommandRunner::new()
+                .respond_with("uniffi-bindgen", bindgen)
+                .respond_with("xcodebuild", fake_xcodebuild));
+
+        let report = builder().try_generate().unwrap();
+
+        let sources = report.swift_packages[0].join("Sources").join("FixtureKit");
+        let headers_dir = sources.join("Headers");
+        assert_eq!(fs::read_to_string(headers_dir.join("module.modulemap")).unwrap(),
+                   "module accountsFFI {\n    header \"accountsFFI.h\"\n    export *\n}\n\n\
+                    module fixture_kitFFI {\n    header \"fixture_kitFFI.h\"\n    export *\n}\n");
+        for file in ["accountsFFI.h", "fixture_kitFFI.h"] {
+            assert!(headers_dir.join(file).is_file(), "missing {}", file);
+        }
+        assert!(!headers_dir.join("accountsFFI.modulemap").exists());
+        assert!(sources.join("accounts.swift").is_file());
+        assert!(sources.join("fixture_kit.swift").is_file());
+
+        fs::remove_file(dir.path().join("src").join("accounts.udl")).unwrap();
+        let plan = builder().plan().unwrap();
+        let bindings_dir = dir.path().join("build").join("universal");
+        assert!(plan.deleted_paths().contains(&&bindings_dir.join("headers")));
+        assert!(plan.deleted_paths().contains(&&bindings_dir.join("swift")));
+
+        builder().try_generate().unwrap();
+        assert_eq!(fs::read_to_string(headers_dir.join("module.modulemap")).unwrap(),
+                   "module fixture_kitFFI {\n    header \"fixture_kitFFI.h\"\n    export *\n}\n");
+        assert!(!headers_dir.join("accountsFFI.h").exists());
+        assert!(!sources.join("accounts.swift").exists());
+        assert!(!bindings_dir.join("swift").join("accounts.swift").exists());
+        assert!(sources.join("fixture_kit.swift").is_file());
+    }
+}
@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use super::command_runner::ProcessCommand;
+use super::platform::Platform;
+
+/// The targets an environment variable or rustflags apply to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetSelector {
+    All,
+    Platform(Platform),
+    Triple(String),
+}
+
+impl TargetSelector {
+    fn matches(&self, platform: Platform, triple: &str) -> bool {
+        match self {
+            TargetSelector::All => true,
+            TargetSelector::Platform(selected) => *selected == platform,
+            TargetSelector::Triple(selected) => selected == triple,
+        }
+    }
+}
+
+impl From<Platform> for TargetSelector {
+    fn from(platform: Platform) -> Self {
+        TargetSelector::Platform(platform)
+    }
+}
+
+/// A target triple, e.g. `"aarch64-apple-ios"`.
+impl From<&str> for TargetSelector {
+    fn from(triple: &str) -> Self {
+        TargetSelector::Triple(triple.to_string())
+    }
+}
+
+/// Environment and rustflags added to the `cargo build` of the targets they select.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct TargetEnvironment {
+    envs: Vec<(TargetSelector, String, String)>,
+    rustflags: Vec<(TargetSelector, Vec<String>)>,
+}
+
+impl TargetEnvironment {
+    pub(crate) fn add_env(&mut self, selector: TargetSelector, key: String, value: String) {
+        self.envs.push((selector, key, value));
+    }
+
+    pub(crate) fn add_rustflags(&mut self, selector: TargetSelector, flags: Vec<String>) {
+        self.rustflags.push((selector, flags));
+    }
+
+    /// Variables for the target, later entries overriding earlier ones with the same key.
+    pub(crate) fn envs(&self, platform: Platform, triple: &str) -> Vec<(String, String)> {
+        let mut envs: Vec<(String, String)> = vec![];
+        for (_, key, value) in self.envs.iter().filter(|(selector, ..)| selector.matches(platform, triple)) {
+            envs.retain(|(k, _)| k != key);
+            envs.push((key.clone(), value.clone()));
+        }
+        envs
+    }
+
+    /// Every rustflag for the target, in the order they were added.
+    pub(crate) fn rustflags(&self, platform: Platform, triple: &str) -> Vec<String> {
+        self.rustflags.iter()
+            .filter(|(selector, _)| selector.matches(platform, triple))
+            .flat_map(|(_, flags)| flags.iter().cloned())
+            .collect()
+    }
+
+    /// Adds the target's rustflags to `command`.
+    ///
+    /// They are passed as `CARGO_ENCODED_RUSTFLAGS`: cargo sets it for build scripts, and it takes
+    /// precedence over `RUSTFLAGS`, so the flags of the calling build would otherwise win.
+    pub(crate) fn apply_rustflags(&self, command: ProcessCommand, platform: Platform, triple: &str) -> ProcessCommand {
+        let rustflags = self.rustflags(platform, triple);
+        if !rustflags.is_empty() {
+            return command.env("CARGO_ENCODED_RUSTFLAGS", rustflags.join("\u{1f}"));
+        }
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Builder, BuildVariant, RecordingCommandRunner};
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn selects_environment_per_platform_and_triple() {
+        let mut environment = TargetEnvironment::default();
+        environment.add_env(TargetSelector::All, "RUSTC_WRAPPER".to_string(), "sccache".to_string());
+        environment.add_env(Platform::MacOs.into(), "MACOSX_DEPLOYMENT_TARGET".to_string(), "11.0".to_string());
+        environment.add_env("x86_64-apple-darwin".into(), "RUSTC_WRAPPER".to_string(), "".to_string());
+        environment.add_rustflags(TargetSelector::All, vec!["-Cdebuginfo=1".to_string()]);
+        environment.add_rustflags("aarch64-apple-darwin".into(), vec!["-C".to_string(), "target-cpu=apple-m1".to_string()]);
+
+        assert_eq!(environment.envs(Platform::Ios, "aarch64-apple-ios"),
+                   vec![("RUSTC_WRAPPER".to_string(), "sccache".to_string())]);
+        assert_eq!(environment.envs(Platform::MacOs, "x86_64-apple-darwin"),
+                   vec![("MACOSX_DEPLOYMENT_TARGET".to_string(), "11.0".to_string()),
+                        ("RUSTC_WRAPPER".to_string(), "".to_string())]);
+        assert_eq!(environment.rustflags(Platform::MacOs, "aarch64-apple-darwin"),
+                   vec!["-Cdebuginfo=1", "-C", "target-cpu=apple-m1"]);
+
+        let command = environment.apply_rustflags(ProcessCommand::new("cargo"), Platform::MacOs, "aarch64-apple-darwin");
+        assert!(command.envs.contains(&("CARGO_ENCODED_RUSTFLAGS".to_string(),
+                                        "-Cdebuginfo=1\u{1f}-C\u{1f}target-cpu=apple-m1".to_string())));
+    }
+
+    #[test]
+    fn passes_target_environment_to_cargo() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+
+        let plan = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new())
+            .with_platforms([Platform::Ios, Platform::MacOs])
+            .with_variants([BuildVariant::release()])
+            .with_target_env(TargetSelector::All, "RUSTC_WRAPPER", "sccache")
+            .with_target_env(Platform::MacOs, "MACOSX_DEPLOYMENT_TARGET", "11.0")
+            .with_rustflags("aarch64-apple-darwin", ["-C", "target-cpu=apple-m1"])
+            .plan()
+            .unwrap();
+
+        let target = plan.targets.iter().find(|f| f.triple == "aarch64-apple-darwin").unwrap();
+        assert_eq!(target.envs, vec![("RUSTC_WRAPPER".to_string(), "sccache".to_string()),
+                                     ("MACOSX_DEPLOYMENT_TARGET".to_string(), "11.0".to_string())]);
+        let ios_target = plan.targets.iter().find(|f| f.triple == "aarch64-apple-ios").unwrap();
+        assert_eq!(ios_target.envs[0], ("IPHONEOS_DEPLOYMENT_TARGET".to_string(), "13.0".to_string()));
+        assert_eq!(target.rustflags, vec!["-C", "target-cpu=apple-m1"]);
+        let ios = plan.commands.iter()
+            .find(|f| f.args.contains(&"aarch64-apple-ios".to_string()) && f.args.contains(&"build".to_string()))
+            .unwrap();
+        assert!(ios.envs.contains(&("RUSTC_WRAPPER".to_string(), "sccache".to_string())));
+        assert!(!ios.envs.iter().any(|(k, _)| k == "CARGO_ENCODED_RUSTFLAGS" || k == "MACOSX_DEPLOYMENT_TARGET"));
+        let mac = plan.commands.iter()
+            .find(|f| f.args.contains(&"aarch64-apple-darwin".to_string()) && f.args.contains(&"build".to_string()))
+            .unwrap();
+        assert!(mac.envs.contains(&("CARGO_ENCODED_RUSTFLAGS".to_string(), "-C\u{1f}target-cpu=apple-m1".to_string())));
+    }
+}
@@ -0,0 +1,97 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::command_runner::{CommandOutput, ProcessCommand};
+
+/// Writes a minimal UniFFI crate with a `staticlib` target into `dir`.
+pub(crate) fn write_fixture_crate(dir: &Path) {
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("Cargo.toml"), r#"[package]
+name = "fixture_kit"
+version = "0.1.0"
+edition = "2021"
+
+[lib]
+crate-type = ["staticlib"]
+
+[workspace]
+"#).unwrap();
+    fs::write(dir.join("src").join("lib.rs"), "").unwrap();
+    fs::write(dir.join("src").join("fixture_kit.udl"), "namespace fixture_kit {};\n").unwrap();
+    write_lockfile(dir);
+}
+
+/// Resolves the fixture crate's Cargo.lock, which `cargo metadata` reads with `--locked`.
+pub(crate) fn write_lockfile(dir: &Path) {
+    let status = std::process::Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .args(["generate-lockfile", "--offline", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Makes the fixture crate depend on a local `uniffi` crate of `version`, standing in for the runtime.
+pub(crate) fn write_uniffi_dependency(dir: &Path, version: &str) {
+    let runtime_dir = dir.join("uniffi");
+    fs::create_dir_all(runtime_dir.join("src")).unwrap();
+    fs::write(runtime_dir.join("Cargo.toml"),
+              format!("[package]\nname = \"uniffi\"\nversion = \"{}\"\nedition = \"2021\"\n", version)).unwrap();
+    fs::write(runtime_dir.join("src").join("lib.rs"), "").unwrap();
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).unwrap()
+        .replace("[workspace]", "[dependencies]\nuniffi = { path = \"uniffi\" }\n\n[workspace]");
+    fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+    write_lockfile(dir);
+}
+
+/// Writes the files `uniffi-bindgen generate` would produce into its `--out-dir`, and answers `--version`.
+pub(crate) fn fake_uniffi_bindgen(command: &ProcessCommand) -> CommandOutput {
+    if command.args == ["--version"] {
+        return CommandOutput::success("uniffi-bindgen 0.28.3\n");
+    }
+    let out_dir = command.args.iter()
+        .skip_while(|f| f.as_str() != "--out-dir")
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(out_dir.join("FixtureKitFFI.h"), "").unwrap();
+    fs::write(out_dir.join("FixtureKitFFI.modulemap"), "module FixtureKitFFI {}\n").unwrap();
+    fs::write(out_dir.join("FixtureKit.swift"), "").unwrap();
+    CommandOutput::success("")
+}
+
+/// Creates the `-output` directory `xcodebuild -create-xcframework` would produce.
+pub(crate) fn fake_xcodebuild(command: &ProcessCommand) -> CommandOutput {
+    let output = command.args.iter()
+        .skip_while(|f| f.as_str() != "-output")
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap();
+    fs::create_dir_all(output.join("ios-arm64")).unwrap();
+    fs::write(output.join("Info.plist"), "").unwrap();
+    CommandOutput::success("")
+}
+
+/// Writes the library and dep-info `cargo build` would produce into its `--target-dir`.
+pub(crate) fn fake_cargo_build(command: &ProcessCommand) -> CommandOutput {
+    let arg = |name: &str| command.args.iter()
+        .skip_while(|f| f.as_str() != name)
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap();
+    let profile = if command.args.contains(&"--release".to_string()) { "release" } else { "debug" };
+    let out_dir = arg("--target-dir").join(arg("--target")).join(profile);
+    let lib_rs = arg("--manifest-path").with_file_name("src").join("lib.rs");
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(out_dir.join("libfixture_kit.a"), "").unwrap();
+    fs::write(out_dir.join("libfixture_kit.d"),
+              format!("{}: {}\n", out_dir.join("libfixture_kit.a").display(), lib_rs.display())).unwrap();
+    CommandOutput::success("")
+}
+
+/// Creates the `-output` file `lipo -create` would produce.
+pub(crate) fn fake_lipo(command: &ProcessCommand) -> CommandOutput {
+    fs::write(&command.args[2], "").unwrap();
+    CommandOutput::success("")
+}
@@ -0,0 +1,255 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use super::command_runner::ProcessCommand;
+use super::error::*;
+
+/// Toolchain used for the targets that are built with `-Zbuild-std`, unless a nightly one is selected.
+const BUILD_STD_CHANNEL: &str = "nightly";
+
+/// The Rust toolchain the per-target builds and the rustup target checks use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Toolchain {
+    /// The toolchain of the cargo invocation running the build script, including rust-toolchain.toml overrides.
+    Inherit {
+        /// `CARGO`, set by cargo for build scripts.
+        cargo: Option<PathBuf>,
+        /// `RUSTC`, set by cargo for build scripts.
+        rustc: Option<PathBuf>,
+        /// `RUSTUP_TOOLCHAIN`, set by the rustup proxies.
+        channel: Option<String>,
+    },
+    /// A rustup toolchain, e.g. `stable` or `nightly-2026-09-01`, used through the rustup proxies.
+    Channel(String),
+    /// A cargo binary. `rustc` is looked up next to it.
+    Cargo(PathBuf),
+}
+
+impl Toolchain {
+    pub(crate) fn from_env() -> Self {
+        Toolchain::Inherit {
+            cargo: env::var_os("CARGO").map(PathBuf::from),
+            rustc: env::var_os("RUSTC").map(PathBuf::from),
+            channel: env::var("RUSTUP_TOOLCHAIN").ok(),
+        }
+    }
+
+    /// Path of a binary installed by cargo, i.e. `$CARGO_HOME/bin/<name>` or `$HOME/.cargo/bin/<name>`.
+    pub(crate) fn cargo_home_bin(name: &str) -> Result<PathBuf> {
+        let cargo_home = match (env::var_os("CARGO_HOME"), env::var_os("HOME")) {
+            (Some(cargo_home), _) => PathBuf::from(cargo_home),
+            (None, Some(home)) => PathBuf::from(home).join(".cargo"),
+            (None, None) => return Err(Error::Toolchain {
+                message: format!("Unable to locate {}: neither CARGO_HOME nor HOME is set.", name)
+            }),
+        };
+        Ok(cargo_home.join("bin").join(name))
+    }
+
+    /// The rustup toolchain to pass as `--toolchain`, if the toolchain is managed by rustup.
+    pub(crate) fn rustup_toolchain(&self) -> Option<&str> {
+        match self {
+            Toolchain::Inherit { channel, .. } => channel.as_deref(),
+            Toolchain::Channel(channel) => Some(channel.as_str()),
+            Toolchain::Cargo(_) => None,
+        }
+    }
+
+    /// Whether rustup can list and install the toolchain's targets. The targets of a cargo binary are
+    /// checked in the sysroot of the rustc next to it instead.
+    pub(crate) fn is_rustup_managed(&self) -> bool {
+        !matches!(self, Toolchain::Cargo(_))
+    }
+
+    /// The toolchain `-Zbuild-std` builds run on: the selected one if it is a nightly, `nightly` otherwise.
+    pub(crate) fn build_std_channel(&self) -> &str {
+        match self.rustup_toolchain() {
+            Some(channel) if channel.starts_with(BUILD_STD_CHANNEL) => channel,
+            _ => BUILD_STD_CHANNEL,
+        }
+    }
+
+    /// `cargo` for building a target, with `build_std` for targets without a prebuilt standard library.
+    pub(crate) fn cargo(&self, build_std: bool) -> Result<ProcessCommand> {
+        match self {
+            Toolchain::Cargo(cargo) => Ok(ProcessCommand::new(cargo)
+                .env("RUSTC", Toolchain::sibling_rustc(cargo)?.to_string_lossy())),
+            _ if build_std => Toolchain::proxy("cargo", self.build_std_channel()),
+            Toolchain::Channel(channel) => Toolchain::proxy("cargo", channel),
+            Toolchain::Inherit { cargo: Some(cargo), .. } => Ok(ProcessCommand::new(cargo)),
+            Toolchain::Inherit { .. } => Ok(ProcessCommand::new(Toolchain::cargo_home_bin("cargo")?)),
+        }
+    }
+
+    /// `rustc` of the toolchain `cargo(build_std)` builds with.
+    pub(crate) fn rustc(&self, build_std: bool) -> Result<ProcessCommand> {
+        match self {
+            Toolchain::Cargo(cargo) => Ok(ProcessCommand::new(Toolchain::sibling_rustc(cargo)?)),
+            _ if build_std => Toolchain::proxy("rustc", self.build_std_channel()),
+            Toolchain::Channel(channel) => Toolchain::proxy("rustc", channel),
+            Toolchain::Inherit { rustc: Some(rustc), .. } => Ok(ProcessCommand::new(rustc)),
+            Toolchain::Inherit { .. } => Ok(ProcessCommand::new("rustc")),
+        }
+    }
+
+    /// Runs `name` through the rustup proxy. The proxy's toolchain also applies to the `rustc` cargo runs,
+    /// as `RUSTC` inherited from the calling build would otherwise point at its own toolchain.
+    fn proxy(name: &str, channel: &str) -> Result<ProcessCommand> {
+        let mut command = ProcessCommand::new(Toolchain::cargo_home_bin(name)?)
+            .arg(format!("+{}", channel));
+        if name == "cargo" {
+            command = command.env("RUSTC", Toolchain::cargo_home_bin("rustc")?.to_string_lossy());
+        }
+        Ok(command)
+    }
+
+    /// The `rustc` next to `cargo`. Any other one may be of another release, whose libraries cargo can't link.
+    fn sibling_rustc(cargo: &Path) -> Result<PathBuf> {
+        let rustc = cargo.with_file_name("rustc");
+        if !rustc.is_file() {
+            return Err(Error::Toolchain {
+                message: format!("No rustc next to the cargo binary {:?}; expected it at {:?}.", cargo, rustc),
+            });
+        }
+        Ok(rustc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use super::Toolchain;
+    use crate::{Builder, CommandOutput, Error, Platform, RecordingCommandRunner};
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn inherits_the_calling_toolchain() {
+        let toolchain = Toolchain::Inherit {
+            cargo: Some(PathBuf::from("/rustup/toolchains/stable/bin/cargo")),
+            rustc: Some(PathBuf::from("/rustup/toolchains/stable/bin/rustc")),
+            channel: Some("stable-aarch64-apple-darwin".to_string()),
+        };
+        assert_eq!(toolchain.cargo(false).unwrap().to_string(), "/rustup/toolchains/stable/bin/cargo");
+        assert_eq!(toolchain.rustc(false).unwrap().to_string(), "/rustup/toolchains/stable/bin/rustc");
+        assert_eq!(toolchain.rustup_toolchain(), Some("stable-aarch64-apple-darwin"));
+        assert_eq!(toolchain.build_std_channel(), "nightly");
+        assert!(toolchain.cargo(true).unwrap().args.starts_with(&["+nightly".to_string()]));
+    }
+
+    #[test]
+    fn pins_a_channel() {
+        let toolchain = Toolchain::Channel("nightly-2026-09-01".to_string());
+        let cargo = toolchain.cargo(false).unwrap();
+        assert!(cargo.program.ends_with("bin/cargo"));
+        assert_eq!(cargo.args, vec!["+nightly-2026-09-01"]);
+        assert!(cargo.envs.iter().any(|(k, v)| k == "RUSTC" && v.ends_with("bin/rustc")));
+        assert_eq!(toolchain.build_std_channel(), "nightly-2026-09-01");
+        assert_eq!(toolchain.rustc(true).unwrap().args, vec!["+nightly-2026-09-01"]);
+    }
+
+    #[test]
+    fn uses_an_explicit_cargo() {
+        let bin = tempfile::tempdir().unwrap();
+        let toolchain = Toolchain::Cargo(bin.path().join("cargo"));
+        assert!(matches!(toolchain.rustc(false), Err(Error::Toolchain { .. })));
+        assert!(matches!(toolchain.cargo(false), Err(Error::Toolchain { .. })));
+
+        fs::write(bin.path().join("rustc"), "").unwrap();
+        let rustc = bin.path().join("rustc").to_string_lossy().to_string();
+        let cargo = toolchain.cargo(true).unwrap();
+        assert_eq!(cargo.program, bin.path().join("cargo").to_string_lossy());
+        assert!(cargo.args.is_empty());
+        assert!(cargo.envs.iter().any(|(k, v)| k == "RUSTC" && *v == rustc));
+        assert_eq!(toolchain.rustup_toolchain(), None);
+        assert!(!toolchain.is_rustup_managed());
+        assert_eq!(toolchain.rustc(false).unwrap().program, rustc);
+    }
+
+    #[test]
+    fn offline_mode_verifies_targets_in_the_sysroot() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let sysroot = tempfile::tempdir().unwrap();
+        let std_dir = sysroot.path().join("lib").join("rustlib").join("aarch64-apple-ios").join("lib");
+        fs::create_dir_all(&std_dir).unwrap();
+        fs::write(std_dir.join("libstd-0123456789abcdef.rlib"), "").unwrap();
+        let builder = || Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new()
+                .respond("--print sysroot", CommandOutput::success(format!("{}\n", sysroot.path().display()))))
+            .with_offline(true);
+
+        let error = builder()
+            .with_platforms([Platform::Ios, Platform::IosSimulator])
+            .plan()
+            .unwrap_err();
+        match error {
+            Error::MissingTargets { triples, sysroot: path, .. } => {
+                assert_eq!(triples, vec!["aarch64-apple-ios-sim", "x86_64-apple-ios"]);
+                assert_eq!(path, sysroot.path());
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        let error = builder()
+            .with_toolchain("nightly-2026-09-01")
+            .with_platforms([Platform::IosSimulator])
+            .plan()
+            .unwrap_err();
+        assert!(error.to_string()
+            .contains("`rustup target add aarch64-apple-ios-sim x86_64-apple-ios --toolchain nightly-2026-09-01`"));
+
+        let plan = builder()
+            .with_platforms([Platform::Ios])
+            .plan()
+            .unwrap();
+        assert!(!plan.commands.iter().any(|f| f.program == "rustup"));
+    }
+
+    #[test]
+    fn builds_and_checks_targets_with_the_selected_toolchain() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+
+        let plan = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new())
+            .with_platforms([Platform::Ios])
+            .with_toolchain("1.80.0")
+            .plan()
+            .unwrap();
+
+        let commands = plan.commands.iter().map(|f| f.to_string()).collect::<Vec<String>>();
+        assert!(commands.contains(&"rustup target add aarch64-apple-ios --toolchain 1.80.0".to_string()));
+        let cargo = plan.commands.iter().find(|f| f.args.contains(&"build".to_string())).unwrap();
+        assert!(cargo.program.ends_with("cargo"));
+        assert_eq!(cargo.args[0], "+1.80.0");
+
+        let sysroot = tempfile::tempdir().unwrap();
+        let std_dir = sysroot.path().join("lib").join("rustlib").join("aarch64-apple-ios").join("lib");
+        fs::create_dir_all(&std_dir).unwrap();
+        fs::write(std_dir.join("libstd-0123456789abcdef.rlib"), "").unwrap();
+        let bin = tempfile::tempdir().unwrap();
+        let rustc = bin.path().join("rustc");
+        fs::write(&rustc, "").unwrap();
+        let runner = Arc::new(RecordingCommandRunner::new()
+            .respond("--print sysroot", CommandOutput::success(format!("{}\n", sysroot.path().display()))));
+        let plan = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner.clone())
+            .with_platforms([Platform::Ios])
+            .with_cargo(bin.path().join("cargo"))
+            .plan()
+            .unwrap();
+        assert!(!runner.calls().iter().chain(&plan.commands).any(|f| f.program == "rustup"));
+        assert!(runner.calls().iter().any(|f| rustc == Path::new(&f.program) && f.args == ["--print", "sysroot"]));
+        let error = Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(runner)
+            .with_platforms([Platform::Ios, Platform::MacOs])
+            .with_cargo(bin.path().join("cargo"))
+            .plan()
+            .unwrap_err();
+        assert!(matches!(error, Error::MissingTargets { toolchain: None, .. }));
+    }
+}
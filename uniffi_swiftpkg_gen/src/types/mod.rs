@@ -1,3 +1,4 @@
 mod swift_package;
 
-pub(crate) use swift_package::*;
\ No newline at end of file
+#[allow(unused)]
+pub(crate) use swift_package::*;
@@ -1,5 +1,5 @@
 
-
+#[allow(unused)]
 pub(crate) struct SwiftPackage {
     name: String,
-}
\ No newline at end of file
+}
@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+use super::error::*;
+
+/// Where UDL files are looked for when neither the `Builder` nor the configuration list any.
+const DEFAULT_PATTERN: &str = "src/*.udl";
+
+/// The UDL files of the crate: paths or glob patterns relative to the crate's directory, minus the exclusions.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct UdlSources {
+    /// Paths or patterns such as `src/**/*.udl`, `src/*.udl` when empty.
+    pub(crate) include: Vec<String>,
+    /// Patterns matched against the path of a file relative to the crate's directory.
+    pub(crate) exclude: Vec<String>,
+}
+
+impl UdlSources {
+    /// Each field of `self`, or of `fallback` where `self` has none.
+    pub(crate) fn or(&self, fallback: &UdlSources) -> UdlSources {
+        let pick = |own: &Vec<String>, other: &Vec<String>| match own.is_empty() {
+            true => other.clone(),
+            false => own.clone(),
+        };
+        UdlSources {
+            include: pick(&self.include, &fallback.include),
+            exclude: pick(&self.exclude, &fallback.exclude),
+        }
+    }
+
+    /// The matching files, in the order of the patterns and sorted by path within a pattern.
+    ///
+    /// The default pattern may match nothing, which puts the crate in library mode. A listed path or pattern
+    /// that matches nothing fails with `Error::NoUdlFiles`, as it is most likely a typo.
+    pub(crate) fn find(&self, crate_dir: &Path) -> Result<Vec<PathBuf>> {
+        let is_default = self.include.is_empty();
+        let include = match is_default {
+            true => vec![DEFAULT_PATTERN.to_string()],
+            false => self.include.clone(),
+        };
+        let exclude = self.exclude.iter()
+            .map(|f| glob::Pattern::new(f).map_err(|e| UdlSources::invalid(f, e)))
+            .collect::<Result<Vec<glob::Pattern>>>()?;
+
+        let mut files: Vec<PathBuf> = vec![];
+        for pattern in &include {
+            // Joined, so the empty dir of a `Cargo.toml` manifest path keeps the pattern relative.
+            // An absolute pattern replaces the crate dir.
+            let full_pattern = PathBuf::from(glob::Pattern::escape(&crate_dir.to_string_lossy())).join(pattern);
+            let mut matches = glob::glob(&full_pattern.to_string_lossy())
+                .map_err(|e| UdlSources::invalid(pattern, e))?
+                .filter_map(|f| f.ok())
+                .filter(|f| f.is_file())
+                .collect::<Vec<PathBuf>>();
+            matches.sort();
+            if matches.is_empty() && !is_default {
+                return Err(Error::NoUdlFiles {
+                    search_path: crate_dir.join(pattern),
+                });
+            }
+            for path in matches {
+                let relative = path.strip_prefix(crate_dir).unwrap_or(&path);
+                if !exclude.iter().any(|f| f.matches_path(relative)) && !files.contains(&path) {
+                    files.push(path);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn invalid(pattern: &str, error: impl std::fmt::Display) -> Error {
+        Error::Configuration {
+            message: format!("Invalid UDL pattern '{}': {}", pattern, error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::{Builder, RecordingCommandRunner};
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn finds_udl_files_in_a_stable_order() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["src/b.udl", "src/a.udl", "src/api/c.udl", "src/api/internal.udl", "src/lib.rs"] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let find = |include: &[&str], exclude: &[&str]| UdlSources {
+            include: include.iter().map(|f| f.to_string()).collect(),
+            exclude: exclude.iter().map(|f| f.to_string()).collect(),
+        }.find(dir.path());
+        let src = dir.path().join("src");
+
+        assert_eq!(find(&[], &[]).unwrap(), vec![src.join("a.udl"), src.join("b.udl")]);
+        assert_eq!(find(&["src/**/*.udl"], &["src/api/internal.udl"]).unwrap(),
+                   vec![src.join("a.udl"), src.join("api").join("c.udl"), src.join("b.udl")]);
+        assert_eq!(find(&["src/b.udl", "src/*.udl"], &[]).unwrap(), vec![src.join("b.udl"), src.join("a.udl")]);
+        assert!(matches!(find(&["src/missing.udl"], &[]), Err(Error::NoUdlFiles { .. })));
+        assert!(matches!(find(&["src/[.udl"], &[]), Err(Error::Configuration { .. })));
+
+        fs::remove_file(src.join("a.udl")).unwrap();
+        fs::remove_file(src.join("b.udl")).unwrap();
+        assert!(find(&[], &[]).unwrap().is_empty());
+    }
+
+    // The crate dir of `--manifest-path Cargo.toml` is empty. `cargo test` runs in the package's directory.
+    #[test]
+    fn finds_udl_files_of_the_crate_in_the_working_directory() {
+        let sources = UdlSources {
+            include: vec!["src/udl.rs".to_string()],
+            exclude: vec![],
+        };
+        assert_eq!(sources.find(Path::new("")).unwrap(), vec![PathBuf::from("src/udl.rs")]);
+    }
+
+    #[test]
+    fn generates_the_listed_udl_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        fs::create_dir_all(dir.path().join("src").join("api")).unwrap();
+        fs::write(dir.path().join("src").join("api").join("accounts.udl"), "namespace accounts {};\n").unwrap();
+        fs::write(dir.path().join("src").join("api").join("internal.udl"), "namespace internal {};\n").unwrap();
+        let builder = || Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_command_runner(RecordingCommandRunner::new());
+        let src = dir.path().join("src");
+
+        let plan = builder().plan().unwrap();
+        assert_eq!(plan.package.udl_files, vec![src.join("fixture_kit.udl")]);
+
+        let plan = builder()
+            .with_udl_file("src/fixture_kit.udl")
+            .with_udl_pattern("src/**/*.udl")
+            .with_udl_exclude("src/api/internal.udl")
+            .plan()
+            .unwrap();
+        assert_eq!(plan.package.udl_files, vec![src.join("fixture_kit.udl"), src.join("api").join("accounts.udl")]);
+
+        assert!(matches!(builder().with_udl_file("src/fixture.udl").plan(), Err(Error::NoUdlFiles { .. })));
+    }
+}
@@ -1,291 +1,738 @@
-use std::fmt::Formatter;
 use std::path::{Path, PathBuf};
-use std::process::{ exit };
-use colored::Colorize;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use super::ext::*;
-use super::command_builder::*;
+use super::build_context::BuildContext;
+use super::command_runner::ProcessCommand;
+use super::plan::PlannedTarget;
+use super::platform::{MinimumVersions, Platform, Slice};
 use super::cargo_package::*;
 use super::shared_constants::*;
+use super::error::*;
+use super::toolchain::Toolchain;
+use super::build_variant::BuildVariant;
+use super::target_env::TargetEnvironment;
+use super::fingerprint::{build_env_vars, dep_info_files, Fingerprint, Fingerprints};
+use super::bindgen::{Bindgen, BindingsSource};
 
-const IOS_TARGETS: [&str; 1] = [ "aarch64-apple-ios" ];
-const IOS_SIM_TARGETS: [&str; 2] = ["aarch64-apple-ios-sim", "x86_64-apple-ios"];
-const MACOS_TARGETS: [&str; 2]= ["aarch64-apple-darwin", "x86_64-apple-darwin"];
-
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub(crate) enum BuildType {
-    Debug,
-    Release
-}
-
-impl std::fmt::Display for BuildType {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let value = match *self {
-            BuildType::Release => "release",
-            BuildType::Debug => "debug",
-        };
-        f.write_str(value )
-    }
-}
+/// Fingerprint key of the uniffi-bindgen stage, which the xcframeworks of every variant share.
+const BINDGEN_STAGE: &str = "uniffi-bindgen";
 
 #[derive(Debug)]
 pub(crate) struct XCFrameworkItem {
-    pub(crate) build_type: BuildType,
+    pub(crate) variant: BuildVariant,
     pub(crate) headers_path: PathBuf,
     pub(crate) swift_files_path: PathBuf,
     pub(crate) xc_frameworks_uri: PathBuf,
     pub(crate) swift_package_build_path: PathBuf,
     pub(crate) swift_package_name: String,
+    /// Platforms the xcframework has a library for.
+    pub(crate) platforms: Vec<Platform>,
+    pub(crate) minimum_versions: MinimumVersions,
 }
 
 struct XCFrameworkBuildItems {
-    build_type: BuildType,
-    ios_lib_path: String,
-    ios_headers_path: String,
-    ios_sim_lib_path: String,
-    ios_sim_headers_path: String,
-    osx_lib_path: String,
-    osx_headers_path: String,
+    variant: BuildVariant,
+    headers_path: PathBuf,
+    /// One fat library per selected slice.
+    lib_paths: Vec<PathBuf>,
+    /// The libraries of the slices' targets, in the same order as the slices' triples.
+    target_lib_paths: Vec<PathBuf>,
 }
 
 pub(crate) struct XCFrameworkBuilder {
-    command: CommandBuilder,
-    //host_arch: String,
+    context: BuildContext,
     crate_package: CargoPackage,
-    build_dir: String,
+    build_dir: PathBuf,
+    slices: Vec<Slice>,
+    offline: bool,
+    toolchain: Toolchain,
+    variants: Vec<BuildVariant>,
+    environment: TargetEnvironment,
+    minimum_versions: MinimumVersions,
+    jobs: usize,
+    fingerprints: Arc<Fingerprints>,
+    /// `rustc -vV` of the toolchain, and of the one `-Zbuild-std` targets are built with.
+    rustc_versions: [OnceLock<String>; 2],
 }
 
 impl XCFrameworkBuilder {
     #[allow(unused)]
-    pub(crate) fn new(crate_package: CargoPackage) -> Self {
-        let command_builder =  CommandBuilder::new();
-        let crate_name = crate_package.crate_name.clone();
-        let inst = XCFrameworkBuilder {
-            command: command_builder,
-            //host_arch,
+    pub(crate) fn new(crate_package: CargoPackage, context: BuildContext) -> Self {
+        let build_dir = crate_package.default_build_dir();
+        let fingerprints = Arc::new(Fingerprints::load(&build_dir, false));
+        XCFrameworkBuilder {
+            context,
             crate_package,
-            build_dir: format!("/tmp/xcframework-{}", crate_name)
-        };
-        inst.init();
-        inst
+            build_dir,
+            slices: Platform::DEFAULT.into_iter().map(Slice::from).collect(),
+            offline: false,
+            toolchain: Toolchain::from_env(),
+            variants: BuildVariant::defaults(),
+            environment: TargetEnvironment::default(),
+            minimum_versions: MinimumVersions::default(),
+            jobs: 1,
+            fingerprints,
+            rustc_versions: Default::default(),
+        }
+    }
+
+    /// Skips the stages whose inputs match `fingerprints`, and saves the new ones into the build dir.
+    #[allow(unused)]
+    pub(crate) fn with_fingerprints(mut self, fingerprints: Arc<Fingerprints>) -> Self {
+        self.fingerprints = fingerprints;
+        self
+    }
+
+    /// Compiles up to `jobs` targets at once, each in its own target dir.
+    #[allow(unused)]
+    pub(crate) fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Deployment targets of the cargo builds, also declared in the generated Package.swift.
+    #[allow(unused)]
+    pub(crate) fn with_minimum_versions(mut self, minimum_versions: MinimumVersions) -> Self {
+        self.minimum_versions = minimum_versions;
+        self
     }
 
-    fn init(&self) {
-        let not_installed_targets = self.get_not_installed_targets();
+    /// The deployment target of the platform, then the variables selected through `with_environment`.
+    fn target_envs(&self, platform: Platform, triple: &str) -> Vec<(String, String)> {
+        let mut envs = vec![(platform.deployment_target_env().to_string(),
+                             self.minimum_versions.version(platform).to_string())];
+        for (key, value) in self.environment.envs(platform, triple) {
+            envs.retain(|(k, _)| *k != key);
+            envs.push((key, value));
+        }
+        envs
+    }
+
+    /// Adds `environment` to the `cargo build` of the targets it selects.
+    #[allow(unused)]
+    pub(crate) fn with_environment(mut self, environment: TargetEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Builds exactly `variants` instead of debug and release.
+    #[allow(unused)]
+    pub(crate) fn with_variants(mut self, variants: Vec<BuildVariant>) -> Self {
+        self.variants = variants;
+        self
+    }
+
+    /// Builds the targets and checks them with `toolchain` instead of the one of the calling build.
+    #[allow(unused)]
+    pub(crate) fn with_toolchain(mut self, toolchain: Toolchain) -> Self {
+        self.toolchain = toolchain;
+        self
+    }
+
+    /// Verifies that the targets are installed instead of installing the missing ones with rustup.
+    #[allow(unused)]
+    pub(crate) fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Builds exactly `slices` into the xcframework instead of the default platforms.
+    #[allow(unused)]
+    pub(crate) fn with_slices(mut self, slices: Vec<Slice>) -> Self {
+        self.slices = slices;
+        self
+    }
+
+    fn triples(&self) -> impl Iterator<Item = &String> {
+        self.slices.iter().flat_map(|f| f.triples.iter())
+    }
+
+    /// Runs the per-target cargo builds in `build_dir` instead of the default one.
+    #[allow(unused)]
+    pub(crate) fn with_build_dir(mut self, build_dir: PathBuf) -> Self {
+        self.build_dir = build_dir;
+        self
+    }
+
+    fn init(&self) -> Result<()> {
+        Slice::validate(&self.slices)?;
+        BuildVariant::validate(&self.variants)?;
+        if self.jobs == 0 {
+            return Err(Error::Configuration {
+                message: "The number of parallel jobs must be at least 1.".to_string(),
+            });
+        }
+        // rustup would act on its default toolchain rather than the one owning an explicit cargo binary.
+        if self.offline || !self.toolchain.is_rustup_managed() {
+            return self.verify_targets();
+        }
+        let not_installed_targets = self.get_not_installed_targets()?;
         for target in not_installed_targets {
-            self.install_rustup_target(target);
+            self.install_rustup_target(target)?;
+        }
+        if self.triples().any(|f| Platform::requires_build_std(f)) {
+            self.install_rust_src()?;
+        }
+        Ok(())
+    }
+
+    /// Checks the sysroot of the toolchain cargo uses, which works without network and without rustup.
+    fn verify_targets(&self) -> Result<()> {
+        let sysroot = self.sysroot(self.toolchain.rustc(false)?)?;
+        let mut missing: Vec<String> = vec![];
+        for triple in self.triples() {
+            if !Platform::requires_build_std(triple)
+                && !XCFrameworkBuilder::sysroot_has_std(&sysroot, triple)
+                && !missing.contains(triple) {
+                missing.push(triple.clone());
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::MissingTargets {
+                triples: missing,
+                sysroot,
+                toolchain: self.toolchain.rustup_toolchain().map(|f| f.to_string()),
+            });
+        }
+
+        if self.triples().any(|f| Platform::requires_build_std(f)) {
+            let sysroot = self.sysroot(self.toolchain.rustc(true)?)?;
+            if !sysroot.join("lib").join("rustlib").join("src").join("rust").join("library").is_dir() {
+                return Err(Error::Toolchain {
+                    message: format!("Tier 3 targets are built with -Zbuild-std, which needs rust-src in {:?}. \
+                    Install it with `rustup component add rust-src --toolchain {}`.",
+                                     sysroot, self.toolchain.build_std_channel())
+                });
+            }
         }
+        Ok(())
     }
 
-    fn get_not_installed_targets(&self) -> Vec<String> {
-        let installed_archs = self.command
-            .args(["rustup target list --installed"])
-            .expect("failed to execute process")
+    fn sysroot(&self, rustc: ProcessCommand) -> Result<PathBuf> {
+        let output = self.context.query(&rustc.args(["--print", "sysroot"]))?;
+        Ok(PathBuf::from(output.stdout.trim()))
+    }
+
+    /// Whether `<sysroot>/lib/rustlib/<triple>/lib` holds a prebuilt `libstd`.
+    pub(crate) fn sysroot_has_std(sysroot: &Path, triple: &str) -> bool {
+        std::fs::read_dir(sysroot.join("lib").join("rustlib").join(triple).join("lib"))
+            .map(|entries| entries
+                .filter_map(|f| f.ok())
+                .any(|f| f.file_name().to_string_lossy().starts_with("libstd-")))
+            .unwrap_or(false)
+    }
+
+    /// `-Zbuild-std` compiles the standard library from the `rust-src` component of the nightly toolchain.
+    fn install_rust_src(&self) -> Result<()> {
+        let channel = self.toolchain.build_std_channel();
+        let installed_components = self.context
+            .query(&ProcessCommand::new("rustup")
+                .args(["component", "list", "--installed", "--toolchain", channel]))
+            .map_err(|e| Error::Toolchain {
+                message: format!("Tier 3 targets need the {} toolchain. Install it with `rustup toolchain install {}`. {}",
+                                 channel, channel, e)
+            })?
+            .utf8_string();
+        if installed_components.iter().any(|f| f.starts_with("rust-src")) {
+            return Ok(());
+        }
+        self.context
+            .run(ProcessCommand::new("rustup")
+                .args(["component", "add", "rust-src", "--toolchain", channel]))
+            .map_err(|e| Error::Toolchain {
+                message: format!("Failed to install rust-src for the {} toolchain. {}", channel, e)
+            })
+    }
+
+    fn get_not_installed_targets(&self) -> Result<Vec<String>> {
+        let installed_archs = self.context
+            .query(&self.rustup(["target", "list", "--installed"]))?
             .utf8_string();
 
-        IOS_TARGETS.into_iter()
-            .chain(IOS_SIM_TARGETS.into_iter())
-            .chain(MACOS_TARGETS.into_iter())
-            .filter(|f| !installed_archs.contains(&f.to_string()))
-            .map(|f| f.to_string())
-            .collect::<Vec<String>>()
-    }
-
-    fn install_rustup_target(&self, target: String) {
-        let status = self.command
-            .args_stream([format!("rustup target add {}", target).as_str()]);
-        if !status.success() {
-            eprintln!("Failed to install rust target: {}. Check your connection to internet ", target);
-            eprintln!("{}. status:{}", "execution failed. ".red(), status);
-            exit(1);
+        let mut not_installed: Vec<String> = vec![];
+        for triple in self.triples() {
+            // Tier 3 targets have no rustup component, their std is built from source.
+            if Platform::requires_build_std(triple) {
+                continue;
+            }
+            if !installed_archs.iter().any(|f| f.trim() == triple) && !not_installed.contains(triple) {
+                not_installed.push(triple.clone());
+            }
+        }
+        Ok(not_installed)
+    }
+
+    fn install_rustup_target(&self, target: String) -> Result<()> {
+        self.context
+            .run(self.rustup(["target", "add", target.as_str()]))
+            .map_err(|e| Error::Toolchain {
+                message: format!("Failed to install rust target: {}. Check your connection to internet. {}",
+                                 target, e)
+            })
+    }
+
+    /// `rustup` acting on the selected toolchain, or on the default one if none is selected.
+    fn rustup<I, S>(&self, args: I) -> ProcessCommand
+        where
+            I: IntoIterator<Item = S>,
+            S: AsRef<std::ffi::OsStr> {
+        let command = ProcessCommand::new("rustup").args(args);
+        match self.toolchain.rustup_toolchain() {
+            Some(toolchain) => command.args(["--toolchain", toolchain]),
+            None => command,
         }
     }
 
     #[allow(unused)]
-    pub(crate) fn build(&self) -> Vec<XCFrameworkItem> {
+    pub(crate) fn build(&self) -> Result<Vec<XCFrameworkItem>> {
+        let (bindgen, bindgen_version) = self.resolve_bindgen()?;
+        self.init()?;
         let mut items: Vec<XCFrameworkItem> = vec![];
-        let (headers_dir, swift_files_dir) = self.build_uniffi_bindgen();
-        let build_items = self.build_targets(&headers_dir);
+        let (headers_dir, swift_files_dir) = self.bindings_dirs();
+        // In library mode the bindings are read from a compiled library, any target's will do.
+        if !self.crate_package.is_library_mode() {
+            self.build_uniffi_bindgen(&bindgen, bindgen_version.clone(), None)?;
+        }
+        let build_items = self.build_targets(&headers_dir)?;
+        if self.crate_package.is_library_mode() {
+            let library = build_items.first().and_then(|f| f.target_lib_paths.first());
+            self.build_uniffi_bindgen(&bindgen, bindgen_version, library)?;
+        }
         for build_item in build_items {
-            let xc_framework_uri = self.build_xc_framework(&build_item);
+            let xc_framework_uri = self.build_xc_framework(&build_item)?;
             let item = XCFrameworkItem {
-                build_type: build_item.build_type,
-                headers_path: Path::new(build_item.ios_headers_path.as_str()).to_path_buf(),
-                xc_frameworks_uri: Path::new(xc_framework_uri.as_str()).to_path_buf(),
-                swift_files_path: Path::new(swift_files_dir.as_str()).to_path_buf(),
+                variant: build_item.variant.clone(),
+                headers_path: build_item.headers_path.clone(),
+                xc_frameworks_uri: xc_framework_uri,
+                swift_files_path: swift_files_dir.clone(),
                 swift_package_build_path: self.crate_package.swift_package_path.clone(),
                 swift_package_name: self.crate_package.swift_package_name.clone(),
+                platforms: self.slices.iter().map(|f| f.platform).collect(),
+                minimum_versions: self.minimum_versions.clone(),
             };
             items.push(item);
         }
-        items
+        self.context.create_dir(&self.build_dir)?;
+        self.context.write_file(self.fingerprints.path(), self.fingerprints.to_json(), true)?;
+        Ok(items)
     }
 
     #[allow(unused)]
-    fn build_xc_framework(&self, item: &XCFrameworkBuildItems) -> String {
-        let out_dir = format!("{}/target/universal/xc",
-                              self.crate_package.crate_dir.to_str().unwrap() );
-        let libs_query = format!("-library {} -headers {} -library {} -headers {} -library {} -headers {}",
-        item.ios_lib_path, item.ios_headers_path,
-        item.ios_sim_lib_path, item.ios_sim_headers_path,
-        item.osx_lib_path, item.osx_headers_path);
-        let output_lib = format!("{}/{}{}.xcframework",
-            out_dir,
-                                 self.crate_package.swift_package_name,
-                                 if item.build_type == BuildType::Debug { "_debug" } else { ""} );
-        let main_query = format!("mkdir -p {0}; rm -rf {2}; xcodebuild -create-xcframework {1} -output {2}",
-                                  out_dir,
-                                 libs_query, output_lib);
-
-        let status = self.command.args_stream([main_query]);
-        if !status.success() {
-            eprintln!("{}{}", "Building xc-framework failed. ".red(), status);
-            exit(1);
+    fn build_xc_framework(&self, item: &XCFrameworkBuildItems) -> Result<PathBuf> {
+        let out_dir = self.universal_dir().join("xc");
+        let output_lib = out_dir.join(format!("{}{}.xcframework",
+                                              self.crate_package.swift_package_name,
+                                              item.variant.suffix));
+
+        let mut command = ProcessCommand::new("xcodebuild").arg("-create-xcframework");
+        for lib_path in &item.lib_paths {
+            command = command.arg("-library").arg(lib_path).arg("-headers").arg(&item.headers_path);
         }
-        output_lib
+        let command = command.arg("-output").arg(&output_lib);
+
+        let stage = format!("xcframework {}", item.variant.name);
+        let fingerprint = self.slices.iter()
+            .fold(Fingerprint::new().add(command.to_string()), |fingerprint, slice| {
+                fingerprint.add(self.fingerprints.current(&XCFrameworkBuilder::lipo_stage(slice, &item.variant)))
+            })
+            .add(self.fingerprints.current(BINDGEN_STAGE))
+            .to_hex();
+        if self.fingerprints.is_fresh(&stage, &fingerprint, &[&output_lib]) {
+            return Ok(output_lib);
+        }
+
+        self.context.create_dir(&out_dir)?;
+        self.context.remove(&output_lib)?;
+        self.context.run(command)?;
+        self.fingerprints.record(&stage, fingerprint);
+        Ok(output_lib)
     }
 
     #[allow(unused)]
-    fn build_targets(&self, headers_path: &str) -> Vec<XCFrameworkBuildItems> {
+    fn build_targets(&self, headers_path: &Path) -> Result<Vec<XCFrameworkBuildItems>> {
+        let targets = self.variants.iter()
+            .flat_map(|variant| self.slices.iter()
+                .flat_map(move |slice| slice.triples.iter().map(move |f| (variant, slice.platform, f.as_str()))))
+            .collect::<Vec<(&BuildVariant, Platform, &str)>>();
+        self.context.update_plan(|plan| {
+            plan.jobs = self.jobs;
+            plan.targets.extend(targets.iter().map(|(variant, platform, triple)| PlannedTarget {
+                variant: variant.name.clone(),
+                slice: platform.to_string(),
+                triple: triple.to_string(),
+                envs: self.target_envs(*platform, triple),
+                rustflags: self.environment.rustflags(*platform, triple),
+            }));
+        });
+        let mut targets_paths = self.compile_targets(&targets)?.into_iter();
+
         let mut items: Vec<XCFrameworkBuildItems> = vec![];
-        let build_types = [BuildType::Debug, BuildType::Release];
-
-        for build_type in build_types {
-            let ios_lib_files = self.build_lipo("ios", &build_type,
-                                                IOS_TARGETS.to_vec().iter()
-                                                    .map(|f| self.compile_for_target(f, build_type))
-                                                    .collect::<Vec<String>>());
-            let ios_sim_lib_files = self.build_lipo("ios_sim", &build_type,
-                                                    IOS_SIM_TARGETS.to_vec().iter()
-                                                        .map(|f| self.compile_for_target(f, build_type))
-                                                        .collect::<Vec<String>>());
-            let osx_lib_files = self.build_lipo("osx", &build_type,
-                                                MACOS_TARGETS.to_vec().iter()
-                                                    .map(|f| self.compile_for_target(f, build_type))
-                                                    .collect::<Vec<String>>());
-            let item = XCFrameworkBuildItems {
-                build_type,
-                ios_lib_path: ios_lib_files,
-                ios_headers_path: headers_path.to_string(),
-                ios_sim_lib_path: ios_sim_lib_files,
-                ios_sim_headers_path: headers_path.to_string(),
-                osx_lib_path: osx_lib_files,
-                osx_headers_path: headers_path.to_string()
-            };
-            items.push(item);
+        for variant in &self.variants {
+            let mut lib_paths: Vec<PathBuf> = vec![];
+            let mut target_lib_paths: Vec<PathBuf> = vec![];
+            for slice in &self.slices {
+                let slice_paths = targets_paths.by_ref().take(slice.triples.len()).collect::<Vec<PathBuf>>();
+                target_lib_paths.extend(slice_paths.iter().cloned());
+                lib_paths.push(self.build_lipo(slice, variant, slice_paths)?);
+            }
+            items.push(XCFrameworkBuildItems {
+                variant: variant.clone(),
+                headers_path: headers_path.to_path_buf(),
+                lib_paths,
+                target_lib_paths,
+            });
         }
-        items
+        Ok(items)
     }
 
-    #[allow(unused)]
-    fn build_uniffi_bindgen(&self) -> (String, String) {
-        let out_dir = format!("{}/target/universal/headers",
-                                 self.crate_package.crate_dir.to_str().unwrap() );
-        let swift_out_dir = format!("{}/target/universal/swift",
-                              self.crate_package.crate_dir.to_str().unwrap() );
-
-        for (path, _name) in &self.crate_package.udl_absolute_files_path {
-            let status = self.command.args_stream([format!("$HOME/.cargo/bin/uniffi-bindgen generate {} --language swift --out-dir {}",
-                                              path.to_str().unwrap(), out_dir ).as_str()]);
-            if !status.success() {
-                eprintln!("{}{}", "execution of uniffi_bindgen failed. ".red(), status);
-                exit(1);
-            }
+    /// Compiles `targets`, up to `jobs` at once, and returns their libraries in the same order.
+    ///
+    /// Once a build fails no other one is started, and the error of the first failed target is returned.
+    /// A dry run lists the builds one after another, as nothing is executed.
+    fn compile_targets(&self, targets: &[(&BuildVariant, Platform, &str)]) -> Result<Vec<PathBuf>> {
+        if self.jobs <= 1 || self.context.is_dry_run() {
+            return targets.iter()
+                .map(|(variant, platform, triple)| self.compile_for_target(triple, *platform, variant))
+                .collect();
+        }
 
-            // rename *.modulemap to module.modulemap
-            let status = self.command.args_stream([format!("mv {0}/*FFI.modulemap {0}/module.modulemap", out_dir)]);
-            if !status.success() {
-                eprintln!("{}{}", "Renaming module map failed. ".red(), status);
-                exit(1);
-            }
-            // Move Swift packages to swift
-            let status = self.command.args_stream([format!("mkdir -p {1}; mv {0}/*.swift {1}/", out_dir, swift_out_dir)]);
-            if !status.success() {
-                eprintln!("{}{}", "Moving swift files failed. ".red(), status);
-                exit(1);
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let results: Mutex<Vec<Option<Result<PathBuf>>>> = Mutex::new(targets.iter().map(|_| None).collect());
+        std::thread::scope(|scope| {
+            for _ in 0..self.jobs.min(targets.len()) {
+                scope.spawn(|| {
+                    while !failed.load(Ordering::SeqCst) {
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        let Some((variant, platform, triple)) = targets.get(index) else {
+                            break;
+                        };
+                        let result = self.compile_for_target(triple, *platform, variant);
+                        if result.is_err() {
+                            failed.store(true, Ordering::SeqCst);
+                        }
+                        if let Ok(mut results) = results.lock() {
+                            results[index] = Some(result);
+                        }
+                    }
+                });
             }
+        });
+        results.into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// The bindgen and its version, checked against the crate's uniffi runtime before anything is installed
+    /// or compiled, as mismatched bindings compile and only fail at runtime.
+    fn resolve_bindgen(&self) -> Result<(Bindgen, Option<String>)> {
+        let bindgen = Bindgen::resolve()?;
+        let uniffi_version = self.crate_package.uniffi_version(self.offline)?;
+        let bindgen_version = bindgen.version(&self.context).map_err(|e| match (e, &bindgen) {
+            // No exit status: the binary could not be spawned at all.
+            (Error::Command { status: None, stderr, .. }, Bindgen::Binary(path)) => Error::BindgenNotFound {
+                path: path.clone(),
+                message: stderr,
+                install_hint: super::bindgen::install_hint(uniffi_version.as_deref()),
+            },
+            (e, _) => e,
+        })?;
+        if let (Some(uniffi_version), Some(bindgen_version)) = (&uniffi_version, &bindgen_version) {
+            bindgen.check_version(uniffi_version, bindgen_version)?;
         }
-        (out_dir, swift_out_dir)
+        Ok((bindgen, bindgen_version))
+    }
+
+    /// Directory of everything built from the targets' libraries: the bindings, the fat libraries
+    /// and the xcframeworks. It is inside the build dir, so worktrees sharing a target dir stay apart.
+    fn universal_dir(&self) -> PathBuf {
+        self.build_dir.join("universal")
     }
 
+    /// Directories of the generated headers and module map, and of the generated Swift files.
+    fn bindings_dirs(&self) -> (PathBuf, PathBuf) {
+        let universal_dir = self.universal_dir();
+        (universal_dir.join("headers"), universal_dir.join("swift"))
+    }
+
+    /// Generates the bindings of the UDL files, or in library mode of `library`.
     #[allow(unused)]
-    fn build_lipo(&self, os: &str, build_type: &BuildType, targets_paths: Vec<String>) -> String {
-        let target_dir = format!("{}/target/universal/{}/{}/",
-                                 self.crate_package.crate_dir.to_str().unwrap(), build_type, os);
-        let target_uri = format!("{}/target/universal/{}/{}/lib{}.a",
-                           self.crate_package.crate_dir.to_str().unwrap(), build_type, os,
-                           self.crate_package.target_name);
-
-        let mut target_str = String::new();
-        let mut count: usize = 1;
-        for value in targets_paths.iter() {
-            target_str.push_str(value);
-            target_str.push(' '); // space in between
-            count += 1;
+    fn build_uniffi_bindgen(&self, bindgen: &Bindgen, bindgen_version: Option<String>,
+                            library: Option<&PathBuf>) -> Result<(PathBuf, PathBuf)> {
+        let (out_dir, swift_out_dir) = self.bindings_dirs();
+        let sources = match library {
+            Some(library) => vec![BindingsSource::Library(library)],
+            None => self.crate_package.udl_absolute_files_path.iter()
+                .map(|(path, _name)| BindingsSource::Udl(path))
+                .collect(),
+        };
+        let commands = sources.iter()
+            .map(|source| bindgen.command(source, &out_dir))
+            .collect::<Vec<ProcessCommand>>();
+
+        // A library changes with the sources of its target, which its cargo stage fingerprints.
+        let fingerprint = sources.iter()
+            .zip(&commands)
+            .fold(Fingerprint::new().add(bindgen_version.unwrap_or_default()), |fingerprint, (source, command)| {
+                let fingerprint = fingerprint.add(command.to_string());
+                match source {
+                    BindingsSource::Udl(path) => fingerprint.add_file(path),
+                    BindingsSource::Library(_) => fingerprint.add(self.fingerprints.current(&self.first_cargo_stage())),
+                }
+            })
+            .to_hex();
+        if self.fingerprints.is_fresh(BINDGEN_STAGE, &fingerprint, &[&out_dir.join("module.modulemap"), &swift_out_dir]) {
+            return Ok((out_dir, swift_out_dir));
+        }
+
+        // Files of a namespace that is gone would otherwise be merged and copied into the packages.
+        self.context.remove(&out_dir)?;
+        self.context.remove(&swift_out_dir)?;
+        for (source, command) in sources.iter().zip(commands) {
+            self.generate_bindings(bindgen, command, source, &out_dir)?;
+        }
+        // Every namespace gets its own `<namespace>FFI.modulemap`, while Xcode only reads `module.modulemap`.
+        // The headers they reference sit next to it, so their module declarations are merged as they are.
+        self.context.merge_matches(&out_dir, "*FFI.modulemap", &out_dir.join("module.modulemap"))?;
+        self.context.create_dir(&swift_out_dir)?;
+        self.context.move_matches_into(&out_dir, "*.swift", &swift_out_dir)?;
+        self.fingerprints.record(BINDGEN_STAGE, fingerprint);
+        Ok((out_dir, swift_out_dir))
+    }
+
+    /// Stage of the first compiled target, whose library the bindings are read from in library mode.
+    fn first_cargo_stage(&self) -> String {
+        match (self.variants.first(), self.slices.first().and_then(|f| f.triples.first())) {
+            (Some(variant), Some(triple)) => XCFrameworkBuilder::cargo_stage(triple, variant),
+            _ => String::new(),
+        }
+    }
+
+    #[cfg(feature = "bindgen")]
+    fn generate_bindings(&self, bindgen: &Bindgen, command: ProcessCommand, source: &BindingsSource, out_dir: &Path) -> Result<()> {
+        match bindgen {
+            Bindgen::Linked => self.context.run_in_process(command, || {
+                let crate_roots = match source {
+                    BindingsSource::Library(_) => self.crate_package.crate_roots(self.offline)?,
+                    BindingsSource::Udl(_) => Default::default(),
+                };
+                super::bindgen::generate_swift_bindings(source, out_dir, &self.crate_package.target_name, crate_roots)
+            }),
+            Bindgen::Binary(_) => self.context.run(command),
         }
+    }
+
+    #[cfg(not(feature = "bindgen"))]
+    fn generate_bindings(&self, _bindgen: &Bindgen, command: ProcessCommand, _source: &BindingsSource, _out_dir: &Path) -> Result<()> {
+        self.context.run(command)
+    }
+
+    #[allow(unused)]
+    fn build_lipo(&self, slice: &Slice, variant: &BuildVariant, targets_paths: Vec<PathBuf>) -> Result<PathBuf> {
+        let os = slice.platform.name();
+        let target_dir = self.universal_dir().join(&variant.name).join(os);
+        let target_uri = target_dir.join(format!("lib{}.a", self.crate_package.target_name));
+
         // Build directory if required
-        let status = self.command.args_stream([format!("mkdir -p {}", target_dir).as_str()]);
-        if !status.success() {
-            eprintln!("Failed to create universal base directory at: {}", target_dir);
-            eprintln!("{}. status:{}", "execution failed. ".red(), status);
-            exit(1);
+        self.context.create_dir(&target_dir)?;
+
+        if targets_paths.is_empty() {
+            return Err(Error::Toolchain {
+                message: format!("Failed to create Fat binary for OS: {}. No target was built.", os)
+            });
         }
+        let command = ProcessCommand::new("lipo")
+            .arg("-create")
+            .arg("-output").arg(&target_uri)
+            .args(&targets_paths);
 
-        if count == 0 {
-            eprintln!("Failed to create Fat binary for OS: {} ", os.red());
-            exit(1);
-        } else if count == 1 {
-            let status = self.command.args_stream([format!("mv {} {}", target_str, target_uri)]);
-            if !status.success() {
-                eprintln!("Unable to move built library from:\n{}\nto\n{}\n for OS: {} in mode: {}",
-                        target_str, target_uri, os, build_type);
-                eprintln!("{}. status:{}", "execution failed. ".red(), status);
-                exit(1);
-            }
-        } else {
-            let status = self.command.args_stream([format!("lipo -create -output  {} {}",
-                                       target_uri, target_str)]);
-            if !status.success() {
-                eprintln!("Unable to move built library from:\n{}\nto\n{}\n for OS: {} in mode: {}",
-                        target_str, target_uri, os, build_type);
-                eprintln!("{}. status:{}", "execution failed. ".red(), status);
-                exit(1);
-            }
+        let stage = XCFrameworkBuilder::lipo_stage(slice, variant);
+        let fingerprint = slice.triples.iter()
+            .fold(Fingerprint::new().add(command.to_string()), |fingerprint, triple| {
+                fingerprint.add(self.fingerprints.current(&XCFrameworkBuilder::cargo_stage(triple, variant)))
+            })
+            .to_hex();
+        if self.fingerprints.is_fresh(&stage, &fingerprint, &[&target_uri]) {
+            return Ok(target_uri);
         }
-        target_uri
+        self.context.run(command)?;
+        self.fingerprints.record(&stage, fingerprint);
+        Ok(target_uri)
+    }
+
+    fn cargo_stage(triple: &str, variant: &BuildVariant) -> String {
+        format!("cargo {} {}", variant.name, triple)
+    }
+
+    fn lipo_stage(slice: &Slice, variant: &BuildVariant) -> String {
+        format!("lipo {} {}", variant.name, slice.platform.name())
+    }
+
+    /// Inputs of a target's cargo build: its command line, which covers the variant's options, the environment
+    /// and the rustflags, the rustflags and profile overrides it inherits, the toolchain version, `Cargo.lock`,
+    /// the manifests and cargo configuration, the UDL files and the sources listed in the dep-info of its last build.
+    fn target_fingerprint(&self, command: &ProcessCommand, build_std: bool, dep_info: &Path) -> Result<String> {
+        let rustc = self.toolchain.rustc(build_std)?;
+        let rustc_version = self.rustc_versions[build_std as usize].get_or_init(|| {
+            self.context.query(&rustc.arg("-vV"))
+                .map(|f| f.stdout)
+                .unwrap_or_default()
+        });
+        let sources = std::fs::read_to_string(dep_info)
+            .map(|f| dep_info_files(&f))
+            .unwrap_or_default();
+        let env_vars = build_env_vars(std::env::vars_os()
+            .map(|(k, v)| (k.to_string_lossy().to_string(), v.to_string_lossy().to_string())));
+        let fingerprint = env_vars.iter()
+            .fold(Fingerprint::new()
+                      .add(command.to_string())
+                      .add(rustc_version)
+                      .add_file(&self.crate_package.workspace_root.join("Cargo.lock")),
+                  |fingerprint, (key, value)| fingerprint.add(key).add(value));
+        Ok(self.crate_package.build_config_files().iter()
+            .chain(self.crate_package.udl_absolute_files_path.iter().map(|(path, _name)| path))
+            .chain(sources.iter())
+            .fold(fingerprint, |fingerprint, path| fingerprint.add_file(path))
+            .to_hex())
     }
 
     #[allow(unused)]
-    fn compile_for_target(&self, target: &str, build_type: BuildType) -> String {
-        let target_dir = format!("{}/target", self.build_dir);
+    fn compile_for_target(&self, target: &str, platform: Platform, variant: &BuildVariant) -> Result<PathBuf> {
+        // A target dir per triple, so parallel builds don't wait on each other's lock and the libraries
+        // stay where they are whatever the number of jobs.
+        let target_dir = self.build_dir.join("target").join(target);
 
         // Skipping builder is important to eliminate the cyclic build process execution.
-        let command_code = format!("{}=true $HOME/.cargo/bin/cargo build --locked -p {} --lib {} --target {} --target-dir {} --manifest-path {}",
-                                   SKIP_UNIFFI_SWIFTPKG_GEN, self.crate_package.crate_name,
-                                   if build_type == BuildType::Debug { "" } else { "--release" },
-                                   target, target_dir,
-                                   self.crate_package.crate_dir
-                                       .join("Cargo.toml").to_str().unwrap()
-        );
-        let status = self.command.args_stream([command_code.as_str()]);
-        if !status.success() {
-            eprintln!("Failed to compile crate name: {}, lib_name: {}, target architecture: {}",
-                             self.crate_package.crate_name, self.crate_package.target_name, target);
-            eprintln!("{}{}", "Argument failed with error: ".red(), status);
-            exit(1);
+        let mut command = self.toolchain.cargo(Platform::requires_build_std(target))?
+            .env(SKIP_UNIFFI_SWIFTPKG_GEN, "true")
+            .args(["build", "--locked", "-p", self.crate_package.crate_name.as_str(), "--lib"]);
+        if Platform::requires_build_std(target) {
+            command = command.arg("-Zbuild-std=std,panic_abort");
+        }
+        command = command
+            .args(variant.cargo.args())
+            .args(["--target", target])
+            .arg("--target-dir").arg(&target_dir)
+            .arg("--manifest-path").arg(self.crate_package.crate_dir.join("Cargo.toml"));
+        for (key, value) in self.target_envs(platform, target) {
+            command = command.env(key, value);
+        }
+        let command = self.environment.apply_rustflags(command, platform, target);
+
+        let profile_dir = target_dir.join(target).join(variant.cargo.profile_dir());
+        let lib_path = profile_dir.join(format!("lib{}.a", self.crate_package.target_name));
+        let dep_info = profile_dir.join(format!("lib{}.d", self.crate_package.target_name));
+        let stage = XCFrameworkBuilder::cargo_stage(target, variant);
+        let build_std = Platform::requires_build_std(target);
+        if self.fingerprints.is_fresh(&stage, &self.target_fingerprint(&command, build_std, &dep_info)?, &[&lib_path]) {
+            return Ok(lib_path);
         }
 
-        format!("{}/{}/{}/lib{}.a", target_dir, target, build_type, self.crate_package.target_name)
+        match self.jobs {
+            1 => self.context.run(command.clone())?,
+            _ => self.context.run_buffered(command.clone(), &format!("{} {}", variant.name, target))?,
+        }
+        // Taken after the build, as its dep-info lists the sources it was built from.
+        self.fingerprints.record(&stage, self.target_fingerprint(&command, build_std, &dep_info)?);
+        Ok(lib_path)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
     use super::XCFrameworkBuilder;
+    use crate::cargo_package::CargoPackage;
+    use crate::config::PackageConfig;
+    use crate::build_context::BuildContext;
+    use crate::command_builder::CommandBuilder;
+    use crate::command_runner::RecordingCommandRunner;
+    use crate::{Builder, CommandOutput, Error, Platform, ProcessCommand};
+    use crate::test_fixtures::*;
+
+    fn cargo_package() -> CargoPackage {
+        let crate_dir = PathBuf::from("/work/my_crate");
+        CargoPackage {
+            swift_package_name: "MyCrate".to_string(),
+            swift_package_path: crate_dir.join("xcode").join("MyCrate"),
+            crate_name: "my_crate".to_string(),
+            target_name: "my_crate".to_string(),
+            lib_src_path: crate_dir.join("src").join("lib.rs"),
+            crate_manifest_path: crate_dir.join("Cargo.toml"),
+            crate_dir: crate_dir.clone(),
+            cargo_relative_path_to_xcode_project: PathBuf::from("../.."),
+            udl_absolute_files_path: vec![(crate_dir.join("src").join("my_crate.udl"), "my_crate.udl".to_string())],
+            udl_relative_files_path: vec![(PathBuf::from("../../src/my_crate.udl"), "my_crate.udl".to_string())],
+            base_bundle_identifier: "com.example".to_string(),
+            workspace_root: crate_dir.clone(),
+            target_dir: crate_dir.join("target"),
+            config: PackageConfig::default(),
+        }
+    }
+
+    fn context() -> BuildContext {
+        BuildContext::new(CommandBuilder::with_runner(Arc::new(RecordingCommandRunner::new())))
+    }
+
+    #[test]
+    fn defaults_build_dir_under_target_swiftpkg() {
+        let xc = XCFrameworkBuilder::new(cargo_package(), context());
+        assert!(xc.build_dir.starts_with("/work/my_crate/target/swiftpkg"));
+        assert!(xc.build_dir.file_name().unwrap().to_string_lossy().starts_with("my_crate-"));
+
+        let xc = xc.with_build_dir(PathBuf::from("/var/build"));
+        assert_eq!(xc.build_dir, PathBuf::from("/var/build"));
+    }
+
     #[test]
-    fn get_rust_up_targets_for() {
-        let xc = XCFrameworkBuilder::new();
-        //xc.get_rust_up_targets_for();
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    fn build_dir_is_namespaced_by_workspace() {
+        let shared_target_dir = PathBuf::from("/shared/target");
+        let mut first = cargo_package();
+        first.target_dir = shared_target_dir.clone();
+        let mut second = cargo_package();
+        second.target_dir = shared_target_dir;
+        second.workspace_root = PathBuf::from("/work/my_crate-worktree");
+
+        assert_eq!(first.default_build_dir(), PathBuf::from("/shared/target/swiftpkg/my_crate-c120283af524351a"));
+        assert_ne!(first.default_build_dir(), second.default_build_dir());
+        assert!(second.default_build_dir().starts_with("/shared/target/swiftpkg"));
+    }
+
+    #[test]
+    fn compiles_targets_in_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture_crate(dir.path());
+        let runner = Arc::new(RecordingCommandRunner::new()
+            .respond_with("uniffi-bindgen", fake_uniffi_bindgen)
+            .respond_with("xcodebuild", fake_xcodebuild));
+        let builder = || Builder::new()
+            .with_crate_manifest_path(dir.path().join("Cargo.toml"))
+            .with_build_dir(dir.path().join("build"))
+            .with_platforms([Platform::Ios, Platform::IosSimulator]);
+
+        builder().with_command_runner(runner.clone()).with_jobs(3).try_generate().unwrap();
+
+        let builds = runner.calls().into_iter()
+            .filter(|f| f.args.contains(&"build".to_string()))
+            .collect::<Vec<ProcessCommand>>();
+        assert_eq!(builds.len(), 6);
+        let target_dir = dir.path().join("build").join("target").join("aarch64-apple-ios-sim");
+        assert!(builds.iter().any(|f| f.args.contains(&"aarch64-apple-ios-sim".to_string())
+            && f.args.contains(&target_dir.to_string_lossy().to_string())));
+        let lipo = runner.calls().into_iter().find(|f| f.program == "lipo" && f.args.len() == 5).unwrap();
+        assert!(lipo.args[3].ends_with("aarch64-apple-ios-sim/aarch64-apple-ios-sim/debug/libfixture_kit.a"));
+        assert!(lipo.args[4].ends_with("x86_64-apple-ios/x86_64-apple-ios/debug/libfixture_kit.a"));
+
+        let failing = Arc::new(RecordingCommandRunner::new()
+            .respond_with("uniffi-bindgen", fake_uniffi_bindgen)
+            .respond("--target x86_64-apple-ios ", CommandOutput::failure(101, "error: linker not found")));
+        let error = builder().with_command_runner(failing.clone()).with_jobs(2).try_generate().unwrap_err();
+        assert!(matches!(error, Error::Command { status: Some(101), .. }));
+        assert!(!failing.calls().iter().any(|f| f.program == "lipo"));
+        assert!(builder().with_jobs(0).plan().is_err());
     }
-}
\ No newline at end of file
+}